@@ -0,0 +1,217 @@
+//! Merkle roots over the header hashes of every completed period of 2016 headers and a root
+//! over the period roots. A client keeping just the last root can check any header of the
+//! completed periods with a proof, without storing the chain.
+//!
+//! Like the transactions tree of a block, the tree of the period roots duplicates the last
+//! node of odd levels, so the root of roots also commits to the number of periods: without
+//! it a proof of the last period could claim a period after it.
+
+use bitcoin::hash;
+use bitcoin::header::BlockHeader;
+use bitcoin::merkle;
+use bitcoin::stream;
+use store::HeaderStore;
+
+/// Proof that a header is in a period and that the period is under the root of roots
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeaderProof {
+    pub height: usize,
+    /// Number of completed periods under the root
+    pub periods: usize,
+    pub period_root: [u8; 32],
+    /// Siblings of the header hash in the tree of the period
+    pub period_branch: Vec<[u8; 32]>,
+    /// Siblings of the period root in the tree of the period roots
+    pub roots_branch: Vec<[u8; 32]>,
+}
+
+impl HeaderProof {
+    /// Check `block_header` is at `height` in the chain committed by the root of roots `root`
+    pub fn verify(&self, block_header: &BlockHeader, root: &[u8; 32]) -> Result<(), &'static str> {
+        let period = self.height / stream::CHUNK_SIZE;
+        if period >= self.periods {
+            return Err("Period not completed");
+        }
+        if self.period_branch.len() != period_depth() || self.roots_branch.len() != depth(self.periods) {
+            return Err("Branch of unexpected length");
+        }
+        let in_period = (self.height % stream::CHUNK_SIZE) as u32;
+        if merkle::branch_root(&block_header.hash(), &self.period_branch, in_period) != self.period_root {
+            return Err("Header not in the period");
+        }
+        let roots_root = merkle::branch_root(&self.period_root, &self.roots_branch, period as u32);
+        match commit(self.periods, &roots_root) == *root {
+            true => Ok(()),
+            false => Err("Period not under the root"),
+        }
+    }
+}
+
+/// Root over the period roots of the completed periods of `block_headers`, a chain starting
+/// from genesis, the same of `Accumulator::root` on a store with the same headers
+pub fn chain_root(block_headers: &[BlockHeader]) -> [u8; 32] {
+    let period_roots: Vec<[u8; 32]> = block_headers
+        .chunks(stream::CHUNK_SIZE)
+        .filter(|period| period.len() == stream::CHUNK_SIZE)
+        .map(|period| merkle::root(&hash::headers(period)))
+        .collect();
+    root(&period_roots)
+}
+
+/// Root of roots of `period_roots`, the roots of the completed periods
+pub fn root(period_roots: &[[u8; 32]]) -> [u8; 32] {
+    commit(period_roots.len(), &merkle::root(period_roots))
+}
+
+/// Hash of the number of periods and the merkle root of their roots
+fn commit(periods: usize, roots_root: &[u8; 32]) -> [u8; 32] {
+    let mut bytes = [0u8; 40];
+    bytes[..8].copy_from_slice(&(periods as u64).to_le_bytes());
+    bytes[8..].copy_from_slice(roots_root);
+    hash::sha256d(&bytes)
+}
+
+/// Length of a branch in a tree of `leaves` leaves
+fn depth(leaves: usize) -> usize {
+    (usize::BITS - leaves.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// Length of the branch of a header in the tree of a period
+fn period_depth() -> usize {
+    depth(stream::CHUNK_SIZE)
+}
+
+/// Period roots of the completed periods of a store, updated incrementally and after reorgs
+#[derive(Clone, Debug, Default)]
+pub struct Accumulator {
+    /// Hash of the last header of the period and root of the period
+    periods: Vec<([u8; 32], [u8; 32])>,
+}
+
+impl Accumulator {
+    pub fn new() -> Accumulator {
+        Accumulator { periods: Vec::new() }
+    }
+
+    /// Hashes of the headers of `period`
+    fn hashes(store: &dyn HeaderStore, period: usize) -> Option<Vec<[u8; 32]>> {
+        let start = period * stream::CHUNK_SIZE;
+        (start..start + stream::CHUNK_SIZE).map(|height| store.hash_at(height)).collect()
+    }
+
+    /// Follow the completed periods of `store`, dropping the ones changed by a reorg
+    pub fn update(&mut self, store: &dyn HeaderStore) {
+        let completed = store.len() / stream::CHUNK_SIZE;
+        let valid = self
+            .periods
+            .iter()
+            .take(completed)
+            .enumerate()
+            .take_while(|&(period, &(last, _))| store.hash_at((period + 1) * stream::CHUNK_SIZE - 1) == Some(last))
+            .count();
+        self.periods.truncate(valid);
+        for period in valid..completed {
+            match Accumulator::hashes(store, period) {
+                Some(hashes) => self.periods.push((hashes[hashes.len() - 1], merkle::root(&hashes))),
+                None => break,
+            }
+        }
+    }
+
+    /// Roots of the completed periods
+    pub fn period_roots(&self) -> Vec<[u8; 32]> {
+        self.periods.iter().map(|&(_, root)| root).collect()
+    }
+
+    /// Root of roots, see `root`
+    pub fn root(&self) -> [u8; 32] {
+        root(&self.period_roots())
+    }
+
+    /// Proof of the header at `height`, if its period is completed
+    pub fn prove(&self, store: &dyn HeaderStore, height: usize) -> Option<HeaderProof> {
+        let period = height / stream::CHUNK_SIZE;
+        let &(last, period_root) = self.periods.get(period)?;
+        let hashes = Accumulator::hashes(store, period)?;
+        if hashes[hashes.len() - 1] != last {
+            return None;
+        }
+        Some(HeaderProof {
+            height,
+            periods: self.periods.len(),
+            period_root,
+            period_branch: merkle::branch(&hashes, height % stream::CHUNK_SIZE),
+            roots_branch: merkle::branch(&self.period_roots(), period),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use accumulator;
+    use accumulator::Accumulator;
+    use bitcoin::merkle;
+    use bitcoin::stream;
+    use store::{HeaderStore, MemoryStore};
+
+    #[test]
+    pub fn test_accumulator() {
+        let mut block_headers = stream::decode(include_bytes!("../examples/2016/0")).unwrap();
+        while block_headers.len() < 3 * 2016 + 10 {
+            let mut block_header = block_headers[block_headers.len() - 1];
+            block_header.prev_blockhash = block_header.hash();
+            block_headers.push(block_header);
+        }
+        let store = MemoryStore::new();
+        store.extend(&block_headers).unwrap();
+        let mut accumulator = Accumulator::new();
+        accumulator.update(&store);
+        let roots = accumulator.period_roots();
+        assert_eq!(roots.len(), 3);
+        let hashes: Vec<[u8; 32]> = block_headers[2016..4032].iter().map(|h| h.hash()).collect();
+        assert_eq!(roots[1], merkle::root(&hashes));
+
+        let root = accumulator.root();
+        assert_eq!(accumulator::chain_root(&block_headers), root);
+        for &height in &[0, 1, 2015, 2016, 5000, 6047] {
+            let proof = accumulator.prove(&store, height).unwrap();
+            assert!(proof.verify(&block_headers[height], &root).is_ok());
+            assert!(proof.verify(&block_headers[height + 1], &root).is_err());
+            assert!(proof.verify(&block_headers[height], &roots[0]).is_err());
+        }
+        assert!(accumulator.prove(&store, 6048).is_none());
+
+        // the last period duplicated in the tree can't stand for a period after it
+        let mut proof = accumulator.prove(&store, 5000).unwrap();
+        proof.height += 2016;
+        proof.roots_branch[0] = roots[2];
+        assert!(proof.verify(&block_headers[5000], &root).is_err());
+        proof.periods = 4;
+        assert!(proof.verify(&block_headers[5000], &root).is_err());
+        let mut proof = accumulator.prove(&store, 5000).unwrap();
+        proof.roots_branch = vec![roots[0]; 40];
+        assert!(proof.verify(&block_headers[5000], &root).is_err());
+        proof.periods = usize::MAX;
+        proof.height = usize::MAX;
+        assert!(proof.verify(&block_headers[5000], &root).is_err());
+
+        // a reorg in the second period
+        let mut fork = block_headers[2999];
+        fork.nonce = [0; 4];
+        let mut fork_headers = vec![fork];
+        while fork_headers.len() < 2000 {
+            let mut block_header = fork_headers[fork_headers.len() - 1];
+            block_header.prev_blockhash = block_header.hash();
+            fork_headers.push(block_header);
+        }
+        store.truncate(2999).unwrap();
+        store.extend(&fork_headers).unwrap();
+        accumulator.update(&store);
+        assert_eq!(accumulator.period_roots().len(), 2);
+        assert_eq!(accumulator.period_roots()[0], roots[0]);
+        assert_ne!(accumulator.period_roots()[1], roots[1]);
+        let proof = accumulator.prove(&store, 3000).unwrap();
+        assert!(proof.verify(&fork_headers[1], &accumulator.root()).is_ok());
+    }
+}
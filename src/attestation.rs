@@ -0,0 +1,203 @@
+//! Tips of the served stream signed by the operator with an ed25519 key, so clients of a
+//! mirror can tell the stream is the one of the operator
+//!
+//! | bytes    | content                                                  |
+//! |----------|----------------------------------------------------------|
+//! | 0..8     | `CHDRSIGN`                                               |
+//! | 8..12    | network message start bytes, as in P2P                   |
+//! | 12..16   | chunk size, little endian                                |
+//! | 16..20   | height of the tip, little endian                         |
+//! | 20..52   | hash of the tip, in the byte order of `BlockHeader::hash` |
+//! | 52..60   | length of the stream in bytes, little endian             |
+//! | 60..92   | root over the period roots, see `accumulator`            |
+//! | 92..124  | public key                                               |
+//! | 124..188 | signature of the bytes 0..92                             |
+
+use crypto::ed25519;
+use accumulator;
+use bitcoin::header::BlockHeader;
+use bitcoin::stream::ChunkSize;
+
+pub const MAGIC: [u8; 8] = *b"CHDRSIGN";
+
+/// Length of the signed message
+const MESSAGE_LEN: usize = 92;
+
+/// Length in bytes of a signed attestation
+pub const LEN: usize = MESSAGE_LEN + 32 + 64;
+
+/// The state of the served stream
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Attestation {
+    pub network: [u8; 4],
+    pub chunk_size: u32,
+    pub height: u32,
+    pub tip_hash: [u8; 32],
+    pub stream_len: u64,
+    pub period_roots_root: [u8; 32],
+}
+
+impl Attestation {
+    /// Attestation of `block_headers`, a chain starting from genesis
+    pub fn new(network: [u8; 4], chunk_size: ChunkSize, block_headers: &[BlockHeader]) -> Option<Attestation> {
+        let tip = block_headers.last()?;
+        Some(Attestation {
+            network,
+            chunk_size: chunk_size.get() as u32,
+            height: (block_headers.len() - 1) as u32,
+            tip_hash: tip.hash(),
+            stream_len: chunk_size.byte_len(block_headers.len()) as u64,
+            period_roots_root: accumulator::chain_root(block_headers),
+        })
+    }
+
+    fn message(&self) -> [u8; MESSAGE_LEN] {
+        let mut result = [0u8; MESSAGE_LEN];
+        result[0..8].copy_from_slice(&MAGIC);
+        result[8..12].copy_from_slice(&self.network);
+        result[12..16].copy_from_slice(&self.chunk_size.to_le_bytes());
+        result[16..20].copy_from_slice(&self.height.to_le_bytes());
+        result[20..52].copy_from_slice(&self.tip_hash);
+        result[52..60].copy_from_slice(&self.stream_len.to_le_bytes());
+        result[60..92].copy_from_slice(&self.period_roots_root);
+        result
+    }
+
+    /// Check the attestation describes `block_headers` up to its height, a chain starting
+    /// from genesis possibly longer than the attested one
+    pub fn check_chain(&self, block_headers: &[BlockHeader]) -> Result<(), &'static str> {
+        let len = self.height as usize + 1;
+        if block_headers.len() < len {
+            return Err("Chain shorter than the attested one");
+        }
+        let chunk_size = ChunkSize::new(self.chunk_size as usize)?;
+        match Attestation::new(self.network, chunk_size, &block_headers[..len]) {
+            Some(ref attestation) if attestation == self => Ok(()),
+            _ => Err("Chain different from the attested one"),
+        }
+    }
+}
+
+/// Key signing the attestations
+pub struct Signer {
+    secret_key: [u8; 64],
+    public_key: [u8; 32],
+}
+
+impl Signer {
+    /// Signer with the ed25519 key generated from the 32 bytes `seed`
+    pub fn new(seed: &[u8; 32]) -> Signer {
+        let (secret_key, public_key) = ed25519::keypair(seed);
+        Signer {
+            secret_key,
+            public_key,
+        }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public_key
+    }
+
+    pub fn sign(&self, attestation: &Attestation) -> SignedAttestation {
+        SignedAttestation {
+            attestation: *attestation,
+            public_key: self.public_key,
+            signature: ed25519::signature(&attestation.message(), &self.secret_key),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct SignedAttestation {
+    pub attestation: Attestation,
+    pub public_key: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+impl SignedAttestation {
+    pub fn as_bytes(&self) -> [u8; LEN] {
+        let mut result = [0u8; LEN];
+        result[..MESSAGE_LEN].copy_from_slice(&self.attestation.message());
+        result[MESSAGE_LEN..MESSAGE_LEN + 32].copy_from_slice(&self.public_key);
+        result[MESSAGE_LEN + 32..].copy_from_slice(&self.signature);
+        result
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<SignedAttestation, &'static str> {
+        if bytes.len() != LEN || bytes[0..8] != MAGIC {
+            return Err("Not a signed attestation");
+        }
+        let array_32 = |from: usize| {
+            let mut result = [0u8; 32];
+            result.copy_from_slice(&bytes[from..from + 32]);
+            result
+        };
+        let mut stream_len = [0u8; 8];
+        stream_len.copy_from_slice(&bytes[52..60]);
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&bytes[MESSAGE_LEN + 32..]);
+        Ok(SignedAttestation {
+            attestation: Attestation {
+                network: [bytes[8], bytes[9], bytes[10], bytes[11]],
+                chunk_size: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+                height: u32::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]),
+                tip_hash: array_32(20),
+                stream_len: u64::from_le_bytes(stream_len),
+                period_roots_root: array_32(60),
+            },
+            public_key: array_32(MESSAGE_LEN),
+            signature,
+        })
+    }
+
+    /// Check the attestation is signed by `public_key`, the key of the operator
+    pub fn verify(&self, public_key: &[u8; 32]) -> Result<&Attestation, &'static str> {
+        if self.public_key != *public_key {
+            return Err("Attestation signed by another key");
+        }
+        match ed25519::verify(&self.attestation.message(), public_key, &self.signature) {
+            true => Ok(&self.attestation),
+            false => Err("Invalid signature"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use attestation::{Attestation, SignedAttestation, Signer};
+    use bitcoin::envelope;
+    use bitcoin::stream;
+
+    #[test]
+    pub fn test_attestation() {
+        let block_headers = stream::decode(include_bytes!("../examples/2016/0")).unwrap();
+        let main = envelope::network_magic("main").unwrap();
+        let attestation = Attestation::new(main, stream::DEFAULT, &block_headers[..1000]).unwrap();
+        assert_eq!(attestation.height, 999);
+        assert_eq!(attestation.stream_len, stream::byte_len(1000) as u64);
+
+        let signer = Signer::new(&[7; 32]);
+        let other = Signer::new(&[8; 32]);
+        let signed = SignedAttestation::parse(&signer.sign(&attestation).as_bytes()).unwrap();
+        assert_eq!(signed.verify(&signer.public_key()), Ok(&attestation));
+        assert!(signed.verify(&other.public_key()).is_err());
+
+        let mut bytes = signer.sign(&attestation).as_bytes();
+        bytes[30] ^= 1;
+        assert!(SignedAttestation::parse(&bytes).unwrap().verify(&signer.public_key()).is_err());
+        assert!(SignedAttestation::parse(&bytes[1..]).is_err());
+
+        assert!(attestation.check_chain(&block_headers).is_ok());
+        assert!(attestation.check_chain(&block_headers[..999]).is_err());
+        let mut fork = block_headers.clone();
+        fork[999].nonce = [0; 4];
+        assert!(attestation.check_chain(&fork).is_err());
+        // a completed period is committed by its root
+        let attestation = Attestation::new(main, stream::DEFAULT, &block_headers).unwrap();
+        fork[999] = block_headers[999];
+        fork[500].nonce = [0; 4];
+        assert!(attestation.check_chain(&block_headers).is_ok());
+        assert!(attestation.check_chain(&fork).is_err());
+    }
+}
@@ -0,0 +1,37 @@
+extern crate compressedheaders;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use compressedheaders::bitcoin::{stream, stream_v2};
+
+/// The v1 stream in `path`, a stream file or a `headersmmap` directory, read without
+/// opening it as a store, so a running server isn't affected
+fn read_stream(path: &Path) -> Vec<u8> {
+    if !path.is_dir() {
+        return fs::read(path).unwrap();
+    }
+    let mut bytes = Vec::new();
+    for index in 0.. {
+        match fs::read(path.join(format!("{:06}", index))) {
+            Ok(chunk) => bytes.extend(chunk),
+            Err(_) => break,
+        }
+    }
+    bytes
+}
+
+/// Print the size of a v1 stream, like the one of `headersstore`, and of the same headers
+/// in the v2 format, with the row of the Readme table
+fn main() {
+    let path = env::args().nth(1).expect("usage: compare <v1 stream file or headersmmap directory>");
+    let bytes = read_stream(Path::new(&path));
+    let block_headers = stream::decode(&bytes).unwrap();
+    let v2 = stream_v2::encode(&block_headers, stream::DEFAULT);
+    assert_eq!(stream_v2::decode(&v2, stream::DEFAULT).unwrap().len(), block_headers.len());
+    let ratio = v2.len() as f64 * 100.0 / bytes.len() as f64;
+    println!("headers {}", block_headers.len());
+    println!("v1 {} bytes", bytes.len());
+    println!("v2 {} bytes, {:.1}% of v1", v2.len(), ratio);
+    println!("| all | 0-{} | {} | {} | {:.1}% |", block_headers.len().saturating_sub(1), bytes.len(), v2.len(), ratio);
+}
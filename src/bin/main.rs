@@ -8,24 +8,44 @@ extern crate tokio_core;
 
 
 use std::thread;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
 use compressedheaders::{server, client};
+use compressedheaders::accumulator::Accumulator;
 use compressedheaders::bitcoin::Config;
+use compressedheaders::store::{HeaderStore, MemoryStore};
+use compressedheaders::store::file::FileStore;
+use compressedheaders::store::mmap::MmapStore;
 
 fn main() {
     let config = Config::read().unwrap();
 
-    let block_headers_bytes = Vec::new();
-    let block_headers_bytes_arc = Arc::new(Mutex::new(block_headers_bytes));
+    let store: Arc<dyn HeaderStore> = match config.store() {
+        Some(path) if config.mmap() => {
+            let mmap_store = MmapStore::open(path).unwrap();
+            println!("Opened {} with {} headers", path.display(), mmap_store.len());
+            Arc::new(mmap_store)
+        }
+        Some(path) => {
+            let file_store = FileStore::open(path).unwrap();
+            println!("Opened {} with {} headers", path.display(), file_store.len());
+            Arc::new(file_store)
+        }
+        None => Arc::new(MemoryStore::new()),
+    };
 
-    let block_headers_bytes_arc_1 = block_headers_bytes_arc.clone();
+    // built by the sync and read by the server
+    let accumulator = Arc::new(RwLock::new(Accumulator::new()));
+
+    let store_1 = store.clone();
+    let config_1 = config.clone();
+    let accumulator_1 = accumulator.clone();
     thread::spawn(move || {
-        server::start(block_headers_bytes_arc_1);
+        server::start(store_1, &config_1, accumulator_1);
     });
 
-    let block_headers_bytes_arc_2 = block_headers_bytes_arc.clone();
+    let store_2 = store.clone();
     let c = thread::spawn(move || {
-        client::start(block_headers_bytes_arc_2, &config);
+        client::start(store_2, &config, accumulator);
     });
 
     let _ = c.join();
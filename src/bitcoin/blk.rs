@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use bitcoin::header::BlockHeader;
+use bitcoin::p2p::MAGIC;
+use bitcoin::source::{headers_from_slice, not_found, HeaderSource};
+use util::uint::Uint256;
+
+struct Entry {
+    block_header: BlockHeader,
+    height: usize,
+    chainwork: Uint256,
+}
+
+/// Headers read from the `blk*.dat` files of a bitcoind `blocks` directory,
+/// connected by `prev_blockhash` and ordered by chainwork
+pub struct BlockIndex {
+    dir: PathBuf,
+    offsets: HashMap<PathBuf, u64>,
+    entries: HashMap<[u8; 32], Entry>,
+    orphans: HashMap<[u8; 32], Vec<BlockHeader>>,
+    tip: Option<[u8; 32]>,
+}
+
+impl BlockIndex {
+    pub fn new(dir: &Path) -> BlockIndex {
+        BlockIndex {
+            dir: dir.to_path_buf(),
+            offsets: HashMap::new(),
+            entries: HashMap::new(),
+            orphans: HashMap::new(),
+            tip: None,
+        }
+    }
+
+    /// Read the blocks appended to the files since the last scan, returns how many were read
+    pub fn scan(&mut self) -> io::Result<usize> {
+        let key = read_xor_key(&self.dir)?;
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.starts_with("blk") && name.ends_with(".dat"),
+                None => false,
+            })
+            .collect();
+        paths.sort();
+
+        let mut read = 0;
+        for path in paths {
+            let offset = self.offsets.get(&path).cloned().unwrap_or(0);
+            let (block_headers, new_offset) = read_file(&path, offset, &key)?;
+            read += block_headers.len();
+            for block_header in block_headers {
+                self.insert(block_header);
+            }
+            self.offsets.insert(path, new_offset);
+        }
+        Ok(read)
+    }
+
+    /// Add a header, it is connected as soon as its parent is known
+    pub fn insert(&mut self, block_header: BlockHeader) {
+        let mut pending = vec![block_header];
+        while let Some(block_header) = pending.pop() {
+            let hash = block_header.hash();
+            if self.entries.contains_key(&hash) {
+                continue;
+            }
+            let parent = match self.entries.get(&block_header.prev_blockhash) {
+                Some(parent) => Some((parent.height + 1, parent.chainwork)),
+                None if hash == BlockHeader::genesis().hash() => Some((0, Uint256::zero())),
+                None => None,
+            };
+            match parent {
+                Some((height, parent_chainwork)) => {
+                    let chainwork = parent_chainwork + block_header.work();
+                    let is_best = match self.tip {
+                        Some(ref tip) => chainwork > self.entries[tip].chainwork,
+                        None => true,
+                    };
+                    if is_best {
+                        self.tip = Some(hash);
+                    }
+                    self.entries.insert(
+                        hash,
+                        Entry {
+                            block_header,
+                            height,
+                            chainwork,
+                        },
+                    );
+                    if let Some(children) = self.orphans.remove(&hash) {
+                        pending.extend(children);
+                    }
+                }
+                None => self.orphans
+                    .entry(block_header.prev_blockhash)
+                    .or_default()
+                    .push(block_header),
+            }
+        }
+    }
+
+    pub fn tip_height(&self) -> Option<usize> {
+        self.tip.map(|tip| self.entries[&tip].height)
+    }
+
+    /// Headers of the chain with most work, indexed by height
+    pub fn best_chain(&self) -> Vec<BlockHeader> {
+        let mut result = Vec::new();
+        let mut current = self.tip;
+        while let Some(hash) = current {
+            let entry = &self.entries[&hash];
+            result.push(entry.block_header);
+            current = match entry.height {
+                0 => None,
+                _ => Some(entry.block_header.prev_blockhash),
+            };
+        }
+        result.reverse();
+        result
+    }
+}
+
+/// Files source, rescanning the directory for new blocks when asked for the tip
+pub struct FilesSource {
+    block_index: BlockIndex,
+    best_chain: Vec<BlockHeader>,
+}
+
+impl FilesSource {
+    pub fn new(dir: &Path) -> FilesSource {
+        FilesSource {
+            block_index: BlockIndex::new(dir),
+            best_chain: Vec::new(),
+        }
+    }
+}
+
+impl HeaderSource for FilesSource {
+    fn tip(&mut self) -> io::Result<usize> {
+        if self.block_index.scan()? > 0 {
+            self.best_chain = self.block_index.best_chain();
+        }
+        match self.best_chain.len() {
+            0 => Err(not_found(0)),
+            len => Ok(len - 1),
+        }
+    }
+
+    fn header_at(&mut self, height: usize) -> io::Result<BlockHeader> {
+        self.best_chain
+            .get(height)
+            .cloned()
+            .ok_or_else(|| not_found(height))
+    }
+
+    fn headers_from(&mut self, height: usize, count: usize) -> io::Result<Vec<BlockHeader>> {
+        headers_from_slice(&self.best_chain, height, count)
+    }
+}
+
+/// Read the best chain contained in the `blocks` directory `dir`
+pub fn import(dir: &Path) -> io::Result<Vec<BlockHeader>> {
+    let mut block_index = BlockIndex::new(dir);
+    block_index.scan()?;
+    Ok(block_index.best_chain())
+}
+
+/// Newer bitcoind obfuscate block files with the 8 bytes key in `xor.dat`
+fn read_xor_key(dir: &Path) -> io::Result<[u8; 8]> {
+    let mut key: [u8; 8] = [0; 8];
+    match File::open(dir.join("xor.dat")) {
+        Ok(mut f) => f.read_exact(&mut key)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+    Ok(key)
+}
+
+/// Read the headers of the complete records starting at `offset`,
+/// returns them with the offset following the last complete record
+fn read_file(path: &Path, offset: u64, key: &[u8; 8]) -> io::Result<(Vec<BlockHeader>, u64)> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(offset))?;
+
+    let mut result = Vec::new();
+    let mut pos = offset;
+    while pos + 8 + 80 <= len {
+        let mut prefix: [u8; 8] = [0; 8];
+        reader.read_exact(&mut prefix)?;
+        unxor(&mut prefix, pos, key);
+        if prefix[0..4] == [0; 4] {
+            break; // preallocated space not yet written
+        }
+        if prefix[0..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("wrong magic in {} at {}", path.display(), pos),
+            ));
+        }
+        let size = u64::from(u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]));
+        if size < 80 || pos + 8 + size > len {
+            break;
+        }
+        let mut header_bytes: [u8; 80] = [0; 80];
+        reader.read_exact(&mut header_bytes)?;
+        unxor(&mut header_bytes, pos + 8, key);
+        result.push(BlockHeader::from_bytes(header_bytes));
+        reader.seek(SeekFrom::Current((size - 80) as i64))?;
+        pos += 8 + size;
+    }
+    Ok((result, pos))
+}
+
+fn unxor(bytes: &mut [u8], offset: u64, key: &[u8; 8]) {
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte ^= key[((offset + i as u64) % 8) as usize];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::blk;
+    use bitcoin::blk::BlockIndex;
+    use bitcoin::header::BlockHeader;
+    use bitcoin::p2p::MAGIC;
+    use bitcoin::stream;
+    use std::env;
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::process;
+
+    fn record(block_header: &BlockHeader) -> Vec<u8> {
+        let mut result = MAGIC.to_vec();
+        result.extend_from_slice(&81u32.to_le_bytes());
+        result.extend_from_slice(&block_header.as_bytes());
+        result.push(0);
+        result
+    }
+
+    #[test]
+    pub fn test_import() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        let dir = env::temp_dir().join(format!("compressedheaders-blk-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // out of order blocks, a stale block and trailing preallocated space
+        let mut stale = block_headers[10];
+        stale.nonce = [0; 4];
+        let mut first = Vec::new();
+        for block_header in block_headers[1000..].iter().chain(&[stale]) {
+            first.extend(record(block_header));
+        }
+        first.extend(vec![0u8; 1000]);
+        let mut second = Vec::new();
+        for block_header in &block_headers[..1000] {
+            second.extend(record(block_header));
+        }
+        second.extend_from_slice(&record(&block_headers[1500])[..50]);
+        fs::write(dir.join("blk00000.dat"), &first).unwrap();
+        fs::write(dir.join("blk00001.dat"), &second).unwrap();
+
+        let chain = blk::import(&dir).unwrap();
+        assert_eq!(chain.len(), 2016);
+        assert_eq!(stream::encode(&chain), test_data);
+
+        let mut block_index = BlockIndex::new(&dir);
+        assert_eq!(block_index.scan().unwrap(), 2017);
+        assert_eq!(block_index.tip_height(), Some(2015));
+        let mut f = OpenOptions::new().append(true).open(dir.join("blk00001.dat")).unwrap();
+        f.write_all(&record(&block_headers[1500])[50..]).unwrap();
+        assert_eq!(block_index.scan().unwrap(), 1);
+        assert_eq!(block_index.scan().unwrap(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use util::hex::FromHex;
+
+/// Mainnet checkpoints of bitcoind, block hashes as shown by bitcoind
+const MAINNET: &[(usize, &str)] = &[
+    (11111, "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d"),
+    (33333, "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6"),
+    (74000, "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20"),
+    (105000, "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97"),
+    (134444, "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe"),
+    (168000, "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763"),
+    (193000, "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317"),
+    (210000, "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e"),
+    (216116, "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e"),
+    (225430, "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932"),
+    (250000, "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214"),
+    (279000, "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40"),
+    (295000, "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983"),
+];
+
+/// Testnet checkpoints of bitcoind
+const TESTNET: &[(usize, &str)] = &[
+    (546, "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70"),
+];
+
+/// Block hashes expected at given heights, a chain not matching them is rejected
+/// whatever its work
+#[derive(Clone, Debug, Default)]
+pub struct Checkpoints {
+    list: Vec<(usize, [u8; 32])>,
+}
+
+impl Checkpoints {
+    pub fn new() -> Checkpoints {
+        Checkpoints { list: Vec::new() }
+    }
+
+    /// The built-in checkpoints of `network`: `main`, `test` or `regtest`, which has none
+    pub fn defaults(network: &str) -> Checkpoints {
+        let defaults = match network {
+            "main" => MAINNET,
+            "test" => TESTNET,
+            _ => &[],
+        };
+        let mut checkpoints = Checkpoints::new();
+        for &(height, hash) in defaults {
+            checkpoints.add(height, parse_hash(hash).unwrap());
+        }
+        checkpoints
+    }
+
+    /// Parse a checkpoint written as `height:hash`, with the hash as shown by bitcoind
+    pub fn parse(value: &str) -> Option<(usize, [u8; 32])> {
+        let mut parts = value.splitn(2, ':');
+        let height = parts.next()?.parse().ok()?;
+        let hash = parse_hash(parts.next()?)?;
+        Some((height, hash))
+    }
+
+    /// Add a checkpoint, replacing the one at the same height
+    pub fn add(&mut self, height: usize, hash: [u8; 32]) {
+        self.list.retain(|&(h, _)| h != height);
+        self.list.push((height, hash));
+        self.list.sort_by_key(|&(h, _)| h);
+    }
+
+    /// Height of the last checkpoint
+    pub fn last_height(&self) -> Option<usize> {
+        self.list.last().map(|&(height, _)| height)
+    }
+
+    /// Check the block hash `hash` at `height` is the checkpointed one, if any
+    pub fn check(&self, height: usize, hash: &[u8; 32]) -> Result<(), &'static str> {
+        match self.list.binary_search_by_key(&height, |&(h, _)| h) {
+            Ok(i) if self.list[i].1 != *hash => Err("doesn't match the checkpoint"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parse a block hash as shown by bitcoind, reversed in the byte order of `BlockHeader::hash`
+fn parse_hash(hex: &str) -> Option<[u8; 32]> {
+    let bytes = hex.from_hex().ok()?;
+    if bytes.len() != 32 {
+        return None;
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&bytes);
+    hash.reverse();
+    Some(hash)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::checkpoint::Checkpoints;
+    use bitcoin::header::BlockHeader;
+
+    #[test]
+    pub fn test_checkpoints() {
+        let mainnet = Checkpoints::defaults("main");
+        assert_eq!(mainnet.last_height(), Some(295000));
+        assert!(Checkpoints::defaults("regtest").last_height().is_none());
+
+        let genesis = BlockHeader::genesis().hash();
+        let (height, hash) = Checkpoints::parse(
+            "0:000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        ).unwrap();
+        assert_eq!((height, hash), (0, genesis));
+        assert!(Checkpoints::parse("0:00").is_none());
+        assert!(Checkpoints::parse("x:00").is_none());
+
+        let mut checkpoints = Checkpoints::new();
+        assert!(checkpoints.check(0, &[0; 32]).is_ok());
+        checkpoints.add(0, [1; 32]);
+        checkpoints.add(0, genesis);
+        assert!(checkpoints.check(0, &genesis).is_ok());
+        assert!(checkpoints.check(0, &[0; 32]).is_err());
+        assert!(checkpoints.check(1, &[0; 32]).is_ok());
+        assert!(mainnet.check(11111, &genesis).is_err());
+    }
+}
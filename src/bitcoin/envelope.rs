@@ -0,0 +1,282 @@
+//! Self describing container of a compressed stream: a fixed size header identifying
+//! the format, the network, the chunk size and the height of the first header
+//!
+//! | bytes  | content                                     |
+//! |--------|---------------------------------------------|
+//! | 0..4   | `CHDR`                                      |
+//! | 4..6   | format version, little endian, currently 1  |
+//! | 6..8   | format flags, little endian, see `FLAG_*`   |
+//! | 8..12  | network message start bytes, as in P2P      |
+//! | 12..16 | chunk size, little endian                   |
+//! | 16..20 | height of the first header, little endian   |
+//! | 20..   | compressed stream                           |
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use bitcoin::header::BlockHeader;
+use bitcoin::p2p;
+use bitcoin::stream;
+use bitcoin::stream::ChunkSize;
+use bitcoin::stream_v2;
+use bitcoin::trailer;
+
+pub const FORMAT_MAGIC: [u8; 4] = *b"CHDR";
+
+pub const VERSION: u16 = 1;
+
+/// Length in bytes of the envelope header
+pub const LEN: usize = 20;
+
+/// The stream is in the format of `stream_v2`, it must start a chunk
+pub const FLAG_V2: u16 = 1;
+
+/// Every complete chunk of the stream is followed by a trailer, see `trailer`
+pub const FLAG_TRAILERS: u16 = 2;
+
+/// Flags understood by this version, any other makes the envelope invalid
+const KNOWN_FLAGS: u16 = FLAG_V2 | FLAG_TRAILERS;
+
+/// Message start bytes of `network`: `main`, `test` or `regtest`
+pub fn network_magic(network: &str) -> Option<[u8; 4]> {
+    match network {
+        "main" => Some(p2p::MAGIC),
+        "test" => Some([0x0b, 0x11, 0x09, 0x07]),
+        "regtest" => Some([0xfa, 0xbf, 0xb5, 0xda]),
+        _ => None,
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub version: u16,
+    pub flags: u16,
+    pub network: [u8; 4],
+    pub chunk_size: u32,
+    pub start_height: u32,
+}
+
+impl Envelope {
+    /// Envelope of the current version for a stream of `network` starting at `start_height`
+    pub fn new(network: [u8; 4], chunk_size: ChunkSize, start_height: usize) -> Envelope {
+        Envelope {
+            version: VERSION,
+            flags: 0,
+            network,
+            chunk_size: chunk_size.get() as u32,
+            start_height: start_height as u32,
+        }
+    }
+
+    /// The same envelope for a `stream_v2` stream
+    pub fn v2(mut self) -> Envelope {
+        self.flags |= FLAG_V2;
+        self
+    }
+
+    pub fn is_v2(&self) -> bool {
+        self.flags & FLAG_V2 != 0
+    }
+
+    /// The same envelope for a stream with trailers
+    pub fn trailers(mut self) -> Envelope {
+        self.flags |= FLAG_TRAILERS;
+        self
+    }
+
+    pub fn has_trailers(&self) -> bool {
+        self.flags & FLAG_TRAILERS != 0
+    }
+
+    pub fn chunk_size(&self) -> ChunkSize {
+        ChunkSize::new(self.chunk_size as usize).unwrap_or(stream::DEFAULT)
+    }
+
+    pub fn as_bytes(&self) -> [u8; LEN] {
+        let mut result = [0u8; LEN];
+        result[0..4].copy_from_slice(&FORMAT_MAGIC);
+        result[4..6].copy_from_slice(&self.version.to_le_bytes());
+        result[6..8].copy_from_slice(&self.flags.to_le_bytes());
+        result[8..12].copy_from_slice(&self.network);
+        result[12..16].copy_from_slice(&self.chunk_size.to_le_bytes());
+        result[16..20].copy_from_slice(&self.start_height.to_le_bytes());
+        result
+    }
+
+    /// Parse and validate the envelope at the start of `bytes`, expected for `network`
+    pub fn parse(bytes: &[u8], network: [u8; 4]) -> Result<Envelope, &'static str> {
+        if bytes.len() < LEN || bytes[0..4] != FORMAT_MAGIC {
+            return Err("Not a compressed headers envelope");
+        }
+        let envelope = Envelope {
+            version: u16::from_le_bytes([bytes[4], bytes[5]]),
+            flags: u16::from_le_bytes([bytes[6], bytes[7]]),
+            network: [bytes[8], bytes[9], bytes[10], bytes[11]],
+            chunk_size: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            start_height: u32::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]),
+        };
+        if envelope.version != VERSION {
+            return Err("Unsupported envelope version");
+        }
+        if envelope.flags & !KNOWN_FLAGS != 0 {
+            return Err("Unknown envelope flags");
+        }
+        if envelope.network != network {
+            return Err("Envelope of another network");
+        }
+        let chunk_size = ChunkSize::new(envelope.chunk_size as usize)?;
+        if envelope.is_v2() && envelope.has_trailers() {
+            return Err("v2 streams have no trailers");
+        }
+        if (envelope.is_v2() || envelope.has_trailers()) && !chunk_size.is_full(envelope.start_height as usize) {
+            return Err("Stream not starting with a chunk");
+        }
+        Ok(envelope)
+    }
+}
+
+/// The stream `bytes`, starting at `start_height`, in an envelope
+pub fn encode(network: [u8; 4], chunk_size: ChunkSize, start_height: usize, bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(LEN + bytes.len());
+    result.extend_from_slice(&Envelope::new(network, chunk_size, start_height).as_bytes());
+    result.extend_from_slice(bytes);
+    result
+}
+
+/// `block_headers`, starting at `start_height` which must start a chunk, encoded as
+/// a `stream_v2` stream in an envelope
+pub fn encode_v2(
+    network: [u8; 4],
+    chunk_size: ChunkSize,
+    start_height: usize,
+    block_headers: &[BlockHeader],
+) -> Vec<u8> {
+    let mut result = Envelope::new(network, chunk_size, start_height).v2().as_bytes().to_vec();
+    result.extend_from_slice(&stream_v2::encode_from(block_headers, start_height, chunk_size));
+    result
+}
+
+/// `block_headers`, starting at `start_height` which must start a chunk, encoded with
+/// trailers in an envelope
+pub fn encode_trailers(
+    network: [u8; 4],
+    chunk_size: ChunkSize,
+    start_height: usize,
+    block_headers: &[BlockHeader],
+) -> Vec<u8> {
+    let mut result = Envelope::new(network, chunk_size, start_height).trailers().as_bytes().to_vec();
+    result.extend_from_slice(&trailer::encode_from(block_headers, start_height, chunk_size));
+    result
+}
+
+/// Validate the envelope and rebuild the headers it contains, `prev` is the header
+/// before the first one, needed when it's compressed in a v1 stream
+pub fn decode(
+    bytes: &[u8],
+    network: [u8; 4],
+    prev: Option<&BlockHeader>,
+) -> Result<(Envelope, Vec<BlockHeader>), &'static str> {
+    let envelope = Envelope::parse(bytes, network)?;
+    let start_height = envelope.start_height as usize;
+    let chunk_size = envelope.chunk_size();
+    let block_headers = if envelope.is_v2() {
+        stream_v2::decode_from(&bytes[LEN..], start_height, chunk_size)?
+    } else if envelope.has_trailers() {
+        trailer::decode_from(&bytes[LEN..], start_height, chunk_size)?
+    } else {
+        chunk_size.decode_from(&bytes[LEN..], start_height, prev)?
+    };
+    Ok((envelope, block_headers))
+}
+
+/// Save the stream `bytes` starting at `start_height` to an envelope file
+pub fn save(
+    path: &Path,
+    network: [u8; 4],
+    chunk_size: ChunkSize,
+    start_height: usize,
+    bytes: &[u8],
+) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&Envelope::new(network, chunk_size, start_height).as_bytes())?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// Load the headers of an envelope file
+pub fn load(
+    path: &Path,
+    network: [u8; 4],
+    prev: Option<&BlockHeader>,
+) -> io::Result<(Envelope, Vec<BlockHeader>)> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    decode(&bytes, network, prev).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::envelope;
+    use bitcoin::envelope::Envelope;
+    use bitcoin::stream;
+    use bitcoin::stream::ChunkSize;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    pub fn test_envelope() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        let main = envelope::network_magic("main").unwrap();
+        let test = envelope::network_magic("test").unwrap();
+
+        let bytes = envelope::encode(main, stream::DEFAULT, 0, &test_data);
+        assert_eq!(&bytes[..4], b"CHDR");
+        let (parsed, decoded) = envelope::decode(&bytes, main, None).unwrap();
+        assert_eq!(parsed, Envelope::new(main, stream::DEFAULT, 0));
+        assert_eq!(stream::encode(&decoded), test_data);
+        assert!(envelope::decode(&bytes, test, None).is_err());
+        assert!(envelope::decode(&test_data, main, None).is_err());
+
+        let start = stream::offset(1000);
+        let bytes = envelope::encode(main, stream::DEFAULT, 1000, &test_data[start..]);
+        assert!(envelope::decode(&bytes, main, None).is_err());
+        let (_, decoded) = envelope::decode(&bytes, main, Some(&block_headers[999])).unwrap();
+        assert_eq!(decoded[1015].hash(), block_headers[2015].hash());
+
+        for (i, value) in &[(4, 2u8), (6, 3), (6, 4), (12, 100)] {
+            let mut bytes = bytes.clone();
+            bytes[*i] = *value;
+            assert!(Envelope::parse(&bytes, main).is_err());
+        }
+
+        let bytes = envelope::encode_v2(main, stream::DEFAULT, 0, &block_headers);
+        assert!(bytes.len() < envelope::LEN + test_data.len());
+        let (parsed, decoded) = envelope::decode(&bytes, main, None).unwrap();
+        assert!(parsed.is_v2());
+        assert_eq!(stream::encode(&decoded), test_data);
+        let mut bytes = bytes.clone();
+        bytes[16] = 1;
+        assert!(Envelope::parse(&bytes, main).is_err());
+
+        let bytes = envelope::encode_trailers(main, stream::DEFAULT, 0, &block_headers);
+        let (parsed, decoded) = envelope::decode(&bytes, main, None).unwrap();
+        assert!(parsed.has_trailers());
+        assert_eq!(stream::encode(&decoded), test_data);
+        let mut bytes = bytes.clone();
+        bytes[envelope::LEN + 1000] ^= 1;
+        assert!(envelope::decode(&bytes, main, None).is_err());
+
+        let path = env::temp_dir().join(format!("compressedheaders-envelope-{}", process::id()));
+        let chunk_size = ChunkSize::new(144).unwrap();
+        let bytes = chunk_size.encode(&block_headers);
+        envelope::save(&path, main, chunk_size, 0, &bytes).unwrap();
+        let (parsed, loaded) = envelope::load(&path, main, None).unwrap();
+        assert_eq!(parsed.chunk_size(), chunk_size);
+        assert_eq!(stream::encode(&loaded), test_data);
+        fs::remove_file(&path).unwrap();
+    }
+}
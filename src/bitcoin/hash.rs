@@ -0,0 +1,111 @@
+//! Double SHA256 on fixed arrays, without allocations. A header is 80 bytes, so its hash is
+//! always three SHA256 compressions with the padding known in advance.
+
+use crypto::digest::Digest;
+use crypto::sha2::{sha256_digest_block, Sha256};
+use bitcoin::header::BlockHeader;
+
+/// SHA256 initial state
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09_e667, 0xbb67_ae85, 0x3c6e_f372, 0xa54f_f53a, 0x510e_527f, 0x9b05_688c, 0x1f83_d9ab, 0x5be0_cd19,
+];
+
+/// Hash of a 32 bytes `first` SHA256, the second round of a double SHA256
+fn second(first: &[u8; 32]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    block[..32].copy_from_slice(first);
+    block[32] = 0x80;
+    block[62..].copy_from_slice(&(32u16 * 8).to_be_bytes());
+    let mut state = INITIAL_STATE;
+    sha256_digest_block(&mut state, &block);
+    to_bytes(&state)
+}
+
+fn to_bytes(state: &[u32; 8]) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    for (bytes, word) in result.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    result
+}
+
+/// Double SHA256 of `bytes`
+pub fn sha256d(bytes: &[u8]) -> [u8; 32] {
+    let mut sha2 = Sha256::new();
+    sha2.input(bytes);
+    let mut first: [u8; 32] = [0; 32];
+    sha2.result(&mut first);
+    second(&first)
+}
+
+/// Double SHA256 of the 80 bytes of a header
+pub fn header(bytes: &[u8; 80]) -> [u8; 32] {
+    let mut state = INITIAL_STATE;
+    sha256_digest_block(&mut state, &bytes[..64]);
+    let mut block = [0u8; 64];
+    block[..16].copy_from_slice(&bytes[64..]);
+    block[16] = 0x80;
+    block[62..].copy_from_slice(&(80u16 * 8).to_be_bytes());
+    sha256_digest_block(&mut state, &block);
+    second(&to_bytes(&state))
+}
+
+/// Write the hashes of `block_headers` in `hashes`, of the same length
+pub fn headers_into(block_headers: &[BlockHeader], hashes: &mut [[u8; 32]]) {
+    assert_eq!(block_headers.len(), hashes.len());
+    for (hash, block_header) in hashes.iter_mut().zip(block_headers) {
+        *hash = header(&block_header.as_bytes());
+    }
+}
+
+/// Hashes of `block_headers`, in the byte order of `BlockHeader::hash`
+pub fn headers(block_headers: &[BlockHeader]) -> Vec<[u8; 32]> {
+    let mut hashes = vec![[0u8; 32]; block_headers.len()];
+    headers_into(block_headers, &mut hashes);
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crypto::digest::Digest;
+    use crypto::sha2::Sha256;
+    use bitcoin::hash;
+    use bitcoin::stream;
+    use util::hex::ToHex;
+
+    fn sha256d_engine(bytes: &[u8]) -> [u8; 32] {
+        let mut first = [0u8; 32];
+        let mut sha2 = Sha256::new();
+        sha2.input(bytes);
+        sha2.result(&mut first);
+        let mut second = [0u8; 32];
+        let mut sha2 = Sha256::new();
+        sha2.input(&first);
+        sha2.result(&mut second);
+        second
+    }
+
+    #[test]
+    pub fn test_hash() {
+        assert_eq!(
+            hash::sha256d(b"").to_hex(),
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456"
+        );
+        for len in &[0, 1, 32, 55, 56, 64, 80, 200] {
+            let bytes: Vec<u8> = (0..*len).map(|i| i as u8).collect();
+            assert_eq!(hash::sha256d(&bytes), sha256d_engine(&bytes));
+        }
+
+        let block_headers = stream::decode(include_bytes!("../../examples/2016/0")).unwrap();
+        let hashes = hash::headers(&block_headers);
+        assert_eq!(hashes.len(), 2016);
+        for (block_header, hash) in block_headers.iter().zip(&hashes) {
+            assert_eq!(*hash, sha256d_engine(&block_header.as_bytes()));
+        }
+        assert_eq!(
+            hashes[2015].to_hex(),
+            "6397bb6abd4fc521c0d3f6071b5650389f0b4551bc40b4e6b067306900000000"
+        );
+    }
+}
@@ -1,14 +1,26 @@
 use bitcoin;
-use crypto::digest::Digest;
-use crypto::sha2::Sha256;
+use bitcoin::hash;
 use util::hex::{FromHex, ToHex};
+use util::uint::Uint256;
 use std::fmt;
 
-static GENESIS_RAW_HEX: &'static str = "0100000000000000000000000000000000000000\
+static GENESIS_RAW_HEX: &str = "0100000000000000000000000000000000000000\
                                         000000000000000000000000000000003ba3edfd\
                                         7a7b12b27ac72c3e67768f617fc81bc3888a5132\
                                         3a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
 
+/// Number of blocks, ending with the block itself, whose median time is its median time past
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Seconds a block time can be ahead of the local time, like bitcoind `MAX_FUTURE_BLOCK_TIME`
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Expected seconds between two difficulty adjustments, two weeks
+pub const TARGET_TIMESPAN: u32 = 14 * 24 * 60 * 60;
+
+/// Blocks between two difficulty adjustments
+pub const DIFFICULTY_INTERVAL: usize = 2016;
+
 #[derive(Copy, Clone, Debug)]
 pub struct BlockHeader {
     // The protocol version. Should always be 1.
@@ -46,6 +58,11 @@ impl fmt::Display for BlockHeader {
     }
 }
 
+impl Default for BlockHeader {
+    fn default() -> BlockHeader {
+        BlockHeader::new()
+    }
+}
 
 impl BlockHeader {
     pub fn new() -> BlockHeader {
@@ -63,22 +80,17 @@ impl BlockHeader {
         let mut genesis_raw_bytes: [u8; 80] = [0; 80];
         let genesis_raw_vec = GENESIS_RAW_HEX.from_hex().unwrap();
         genesis_raw_bytes.clone_from_slice(&genesis_raw_vec);
-        let b = BlockHeader::from_bytes(genesis_raw_bytes);
-        return b;
+        BlockHeader::from_bytes(genesis_raw_bytes)
     }
 
     pub fn as_bytes(&self) -> [u8; 80] {
         let mut result: [u8; 80] = [0; 80];
-        let mut vec: Vec<u8> = Vec::new();
-        vec.extend_from_slice(&self.version);
-        vec.extend_from_slice(&self.prev_blockhash);
-        vec.extend_from_slice(&self.merkle_root);
-        vec.extend_from_slice(&self.time);
-        vec.extend_from_slice(&self.bits);
-        vec.extend_from_slice(&self.nonce);
-        for (idx, el) in vec.into_iter().enumerate() {
-            result[idx] = el;
-        }
+        result[0..4].copy_from_slice(&self.version);
+        result[4..36].copy_from_slice(&self.prev_blockhash);
+        result[36..68].copy_from_slice(&self.merkle_root);
+        result[68..72].copy_from_slice(&self.time);
+        result[72..76].copy_from_slice(&self.bits);
+        result[76..80].copy_from_slice(&self.nonce);
         result
     }
 
@@ -139,36 +151,129 @@ impl BlockHeader {
         }
     }
 
+    /// The block time in seconds since the unix epoch
+    pub fn timestamp(&self) -> u32 {
+        u32::from_le_bytes(self.time)
+    }
+
+    /// Check the time is after the median time past of the previous block, `prev_headers`
+    /// are the blocks preceding this one, only the last `MEDIAN_TIME_SPAN` are considered
+    pub fn check_time(&self, prev_headers: &[BlockHeader]) -> Result<(), &'static str> {
+        if prev_headers.is_empty() {
+            return Ok(());
+        }
+        let start = prev_headers.len().saturating_sub(MEDIAN_TIME_SPAN);
+        match self.timestamp() > median_time(&prev_headers[start..]) {
+            true => Ok(()),
+            false => Err("time not after the median time past"),
+        }
+    }
+
+    /// Check the time is at most `MAX_FUTURE_BLOCK_TIME` seconds after `now`
+    pub fn check_future_time(&self, now: u32) -> Result<(), &'static str> {
+        match self.timestamp() <= now.saturating_add(MAX_FUTURE_BLOCK_TIME) {
+            true => Ok(()),
+            false => Err("time too far in the future"),
+        }
+    }
+
+    /// Check the hash doesn't exceed the target
+    pub fn check_pow(&self) -> Result<(), &'static str> {
+        self.check_pow_hash(&self.hash())
+    }
+
+    /// Check `hash`, the hash of this header already computed, doesn't exceed the target
+    pub fn check_pow_hash(&self, hash: &[u8; 32]) -> Result<(), &'static str> {
+        let target = self.target();
+        if target.is_zero() || target > pow_limit() {
+            return Err("invalid bits");
+        }
+        match Uint256::from_le_bytes(*hash) <= target {
+            true => Ok(()),
+            false => Err("hash above the target"),
+        }
+    }
+
+    /// Check this header, with hash `hash`, follows `prev`, with hash `prev_hash`, at `height`
+    /// with the bits and the proof of work required on mainnet. At the start of a period the
+    /// bits are retargeted from `period_first`, the first header of the previous period,
+    /// otherwise they are the ones of `prev`
+    pub fn check_next(
+        &self,
+        hash: &[u8; 32],
+        height: usize,
+        prev: &BlockHeader,
+        prev_hash: &[u8; 32],
+        period_first: Option<&BlockHeader>,
+    ) -> Result<(), &'static str> {
+        if self.prev_blockhash != *prev_hash {
+            return Err("doesn't connect to the previous header");
+        }
+        let bits = match (height.is_multiple_of(DIFFICULTY_INTERVAL), period_first) {
+            (false, _) => prev.bits,
+            (true, Some(period_first)) => next_bits(period_first, prev),
+            (true, None) => return Err("missing the first header of the previous period"),
+        };
+        if self.bits != bits {
+            return Err("has unexpected bits");
+        }
+        self.check_pow_hash(hash)
+    }
+
+    /// The target the hash must not exceed, decoded from `bits`
+    pub fn target(&self) -> Uint256 {
+        Uint256::from_compact(u32::from_le_bytes(self.bits))
+    }
+
+    /// Expected number of hashes to find this header, like bitcoind `GetBlockProof`
+    pub fn work(&self) -> Uint256 {
+        let target = self.target();
+        if target.is_zero() {
+            return Uint256::zero();
+        }
+        (!target / (target + Uint256::from_u64(1))) + Uint256::from_u64(1)
+    }
+
     pub fn hash_be(&self) -> [u8; 32] {
         let mut hash = self.hash();
         hash.reverse();
         hash
     }
     pub fn hash(&self) -> [u8; 32] {
-        let mut sha2 = Sha256::new();
-        sha2.input(&self.as_bytes());
-        let mut first: [u8; 32] = [0; 32];
-        sha2.result(&mut first);
-        let mut sha2b = Sha256::new();
-        sha2b.input(&first);
-
-        //TODO FIX nonsense passing from string
-        let bytes: Vec<u8> = (&sha2b.result_str()).from_hex().unwrap();
+        hash::header(&self.as_bytes())
+    }
+}
 
-        let mut result: [u8; 32] = [0; 32];
-        result.clone_from_slice(&bytes);
+/// The highest target allowed on mainnet
+pub fn pow_limit() -> Uint256 {
+    !Uint256::zero() >> 32
+}
 
-        result
-    }
+/// The bits of the first header of a chunk, given the `first` and `last` headers of the
+/// previous chunk, like bitcoind `CalculateNextWorkRequired` on mainnet
+pub fn next_bits(first: &BlockHeader, last: &BlockHeader) -> [u8; 4] {
+    let timespan = i64::from(last.timestamp()) - i64::from(first.timestamp());
+    let min = i64::from(TARGET_TIMESPAN / 4);
+    let max = i64::from(TARGET_TIMESPAN * 4);
+    let timespan = timespan.clamp(min, max) as u64;
+    let target = last.target().wrapping_mul_u64(timespan) / Uint256::from_u64(u64::from(TARGET_TIMESPAN));
+    target.min(pow_limit()).to_compact().to_le_bytes()
 }
 
+/// Median of the times of `block_headers`, the median time past of the last one when they
+/// are the `MEDIAN_TIME_SPAN` blocks ending with it, or all the blocks from genesis if fewer
+pub fn median_time(block_headers: &[BlockHeader]) -> u32 {
+    let mut times: Vec<u32> = block_headers.iter().map(BlockHeader::timestamp).collect();
+    times.sort_unstable();
+    times[times.len() / 2]
+}
 
 fn transform_u32_to_reversed_array_of_u8(x: u32) -> [u8; 4] {
     let b1: u8 = ((x >> 24) & 0xff) as u8;
     let b2: u8 = ((x >> 16) & 0xff) as u8;
     let b3: u8 = ((x >> 8) & 0xff) as u8;
     let b4: u8 = (x & 0xff) as u8;
-    return [b4, b3, b2, b1];
+    [b4, b3, b2, b1]
 }
 
 fn clone_into_array<A, T>(slice: &[T]) -> A
@@ -198,7 +303,9 @@ fn to_reversed_array_of_32(mut vec: Vec<u8>) -> [u8; 32] {
 #[cfg(test)]
 mod tests {
 
+    use bitcoin::header;
     use bitcoin::header::BlockHeader;
+    use bitcoin::stream;
     use util::hex::{FromHex, ToHex};
     use bitcoin::header::GENESIS_RAW_HEX;
 
@@ -212,6 +319,15 @@ mod tests {
         );
     }
 
+    #[test]
+    pub fn test_work() {
+        let g = BlockHeader::genesis();
+        assert_eq!(
+            format!("{:?}", g.work()),
+            "0000000000000000000000000000000000000000000000000000000100010001"
+        );
+    }
+
     #[test]
     pub fn test_as_compressed_bytes() {
         let g = BlockHeader::genesis();
@@ -234,6 +350,57 @@ mod tests {
         );
     }
 
+    #[test]
+    pub fn test_check_time() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        for height in 1..block_headers.len() {
+            assert!(block_headers[height].check_time(&block_headers[..height]).is_ok());
+        }
+        let mut block_header = block_headers[100];
+        let median = header::median_time(&block_headers[89..100]);
+        block_header.time = median.to_le_bytes();
+        assert!(block_header.check_time(&block_headers[..100]).is_err());
+        block_header.time = (median + 1).to_le_bytes();
+        assert!(block_header.check_time(&block_headers[..100]).is_ok());
+
+        let now = block_header.timestamp();
+        assert!(block_header.check_future_time(now - header::MAX_FUTURE_BLOCK_TIME).is_ok());
+        assert!(block_header.check_future_time(now - header::MAX_FUTURE_BLOCK_TIME - 1).is_err());
+    }
+
+    #[test]
+    pub fn test_check_pow_next_bits() {
+        let chunk_20 = stream::decode(include_bytes!("../../examples/2016/20")).unwrap();
+        let chunk_21 = stream::decode(include_bytes!("../../examples/2016/21")).unwrap();
+        assert!(chunk_20.iter().chain(chunk_21.iter()).all(|h| h.check_pow().is_ok()));
+        assert_eq!(header::next_bits(&chunk_20[0], &chunk_20[2015]), chunk_21[0].bits);
+        assert_ne!(chunk_20[0].bits, chunk_21[0].bits);
+
+        let genesis = BlockHeader::genesis();
+        let mut late = genesis;
+        late.time = (genesis.timestamp() + 5 * header::TARGET_TIMESPAN).to_le_bytes();
+        assert_eq!(header::next_bits(&genesis, &late), genesis.bits);
+        let mut block_header = chunk_21[1];
+        block_header.nonce = [0; 4];
+        assert!(block_header.check_pow().is_err());
+
+        let hash = |h: &BlockHeader| h.hash();
+        let check = |i: usize, first: Option<&BlockHeader>| {
+            let prev = match i {
+                0 => &chunk_20[2015],
+                i => &chunk_21[i - 1],
+            };
+            chunk_21[i].check_next(&hash(&chunk_21[i]), 42336 + i, prev, &hash(prev), first)
+        };
+        assert!(check(0, Some(&chunk_20[0])).is_ok());
+        assert!(check(0, Some(&chunk_20[1])).is_err());
+        assert!(check(0, None).is_err());
+        assert!(check(1, None).is_ok());
+        assert!(chunk_21[2].check_next(&hash(&chunk_21[2]), 42338, &chunk_21[0], &hash(&chunk_21[0]), None).is_err());
+        assert!(block_header.check_next(&hash(&block_header), 42337, &chunk_21[0], &hash(&chunk_21[0]), None).is_err());
+    }
+
     #[test]
     pub fn test_block_headers_reconstruct() {
         let test_data_144 = include_bytes!("../../examples/144/0").to_vec();
@@ -303,7 +470,7 @@ mod tests {
         for i in 0..chunk_size - 1 {
             let mut compressed_block_bytes: [u8; 44] = [0; 44];
             let start = (i * 44 + 80) as usize;
-            let end = (start + 44) as usize;
+            let end = start + 44;
             compressed_block_bytes.clone_from_slice(&test_data[start..end]);
             let current_as_block: BlockHeader =
                 BlockHeader::from_compressed_bytes(compressed_block_bytes, prev_hash, prev_diff);
@@ -0,0 +1,334 @@
+//! Merkle trees of the transactions of a block, hashes are in the byte order of
+//! `BlockHeader::merkle_root`, reversed compared to the txids shown by bitcoind
+
+use bitcoin::hash::sha256d;
+use bitcoin::header::BlockHeader;
+use bitcoin::p2p;
+
+/// More transactions than fit in a block, as for bitcoind
+const MAX_TRANSACTIONS: u32 = 1_000_000 / 60;
+
+/// Length of the longest branch of a transaction in a block
+pub const MAX_DEPTH: usize = (32 - MAX_TRANSACTIONS.leading_zeros()) as usize;
+
+/// Hash of two nodes of the tree
+pub fn parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut bytes = [0u8; 64];
+    bytes[..32].copy_from_slice(left);
+    bytes[32..].copy_from_slice(right);
+    sha256d(&bytes)
+}
+
+/// Root of the tree of `leaves`, duplicating the last node of odd levels
+pub fn root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| parent(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+    }
+    level.first().cloned().unwrap_or([0; 32])
+}
+
+/// Siblings from the leaf up of the leaf at `index`, see `branch_root`
+pub fn branch(leaves: &[[u8; 32]], mut index: usize) -> Vec<[u8; 32]> {
+    let mut result = Vec::new();
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        result.push(*level.get(index ^ 1).unwrap_or(&level[index]));
+        level = level
+            .chunks(2)
+            .map(|pair| parent(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+        index /= 2;
+    }
+    result
+}
+
+/// Root of the tree containing `txid` at position `index`, with `branch` the siblings
+/// from the leaf up
+pub fn branch_root(txid: &[u8; 32], branch: &[[u8; 32]], index: u32) -> [u8; 32] {
+    let mut hash = *txid;
+    for (level, sibling) in branch.iter().enumerate() {
+        hash = match index.checked_shr(level as u32).unwrap_or(0) & 1 {
+            0 => parent(&hash, sibling),
+            _ => parent(sibling, &hash),
+        };
+    }
+    hash
+}
+
+/// Transactions of a block proved by a subset of its merkle tree, as in BIP 37
+#[derive(Clone, Debug)]
+pub struct PartialMerkleTree {
+    pub transactions: u32,
+    pub hashes: Vec<[u8; 32]>,
+    pub bits: Vec<bool>,
+}
+
+/// The txids matched by a partial merkle tree and the root they commit to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matches {
+    pub root: [u8; 32],
+    /// Position in the block and txid of the matched transactions
+    pub txids: Vec<(u32, [u8; 32])>,
+}
+
+impl PartialMerkleTree {
+    /// Parse the tree, returning it with the number of bytes consumed
+    pub fn parse(bytes: &[u8]) -> Result<(PartialMerkleTree, usize), &'static str> {
+        if bytes.len() < 4 {
+            return Err("Partial merkle tree truncated");
+        }
+        let transactions = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let mut pos = 4;
+        let (count, len) = p2p::read_var_int(&bytes[pos..]).map_err(|_| "Invalid hash count")?;
+        pos += len;
+        if (bytes.len() - pos) as u64 / 32 < count {
+            return Err("Partial merkle tree truncated");
+        }
+        let mut hashes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(&bytes[pos..pos + 32]);
+            hashes.push(hash);
+            pos += 32;
+        }
+        let (count, len) = p2p::read_var_int(&bytes[pos..]).map_err(|_| "Invalid flag count")?;
+        pos += len;
+        if ((bytes.len() - pos) as u64) < count {
+            return Err("Partial merkle tree truncated");
+        }
+        let flags = &bytes[pos..pos + count as usize];
+        let bits = (0..flags.len() * 8).map(|i| flags[i / 8] >> (i % 8) & 1 == 1).collect();
+        let tree = PartialMerkleTree {
+            transactions,
+            hashes,
+            bits,
+        };
+        Ok((tree, pos + count as usize))
+    }
+
+    /// Number of nodes at `height` from the leaves
+    fn width(&self, height: u32) -> u32 {
+        ((u64::from(self.transactions) + (1 << height) - 1) >> height) as u32
+    }
+
+    /// Rebuild the root and the matched txids, with the same checks of bitcoind
+    pub fn extract(&self) -> Result<Matches, &'static str> {
+        if self.transactions == 0 || self.transactions > MAX_TRANSACTIONS {
+            return Err("Invalid number of transactions");
+        }
+        if self.hashes.len() > self.transactions as usize || self.bits.len() < self.hashes.len() {
+            return Err("Too many hashes");
+        }
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        let mut used = (0, 0);
+        let mut txids = Vec::new();
+        let root = self.traverse(height, 0, &mut used, &mut txids)?;
+        if used.0.div_ceil(8) != self.bits.len().div_ceil(8) || used.1 != self.hashes.len() {
+            return Err("Unused flags or hashes");
+        }
+        Ok(Matches { root, txids })
+    }
+
+    /// Hash of the node at `height` and `pos`, `used` counts the flags and hashes consumed
+    fn traverse(
+        &self,
+        height: u32,
+        pos: u32,
+        used: &mut (usize, usize),
+        txids: &mut Vec<(u32, [u8; 32])>,
+    ) -> Result<[u8; 32], &'static str> {
+        let parent_of_match = *self.bits.get(used.0).ok_or("Not enough flags")?;
+        used.0 += 1;
+        if height == 0 || !parent_of_match {
+            let hash = *self.hashes.get(used.1).ok_or("Not enough hashes")?;
+            used.1 += 1;
+            if height == 0 && parent_of_match {
+                txids.push((pos, hash));
+            }
+            return Ok(hash);
+        }
+        let left = self.traverse(height - 1, pos * 2, used, txids)?;
+        let right = match pos * 2 + 1 < self.width(height - 1) {
+            true => self.traverse(height - 1, pos * 2 + 1, used, txids)?,
+            false => left,
+        };
+        // an identical right node would allow a different tree with the same root, CVE-2012-2459
+        if pos * 2 + 1 < self.width(height - 1) && right == left {
+            return Err("Duplicated node");
+        }
+        Ok(parent(&left, &right))
+    }
+}
+
+/// Proof of the inclusion of transactions in a block, as returned by `gettxoutproof`
+#[derive(Clone, Debug)]
+pub struct TxOutProof {
+    pub block_header: BlockHeader,
+    pub tree: PartialMerkleTree,
+}
+
+impl TxOutProof {
+    pub fn parse(bytes: &[u8]) -> Result<TxOutProof, &'static str> {
+        if bytes.len() < 80 {
+            return Err("Proof truncated");
+        }
+        let mut header_bytes = [0u8; 80];
+        header_bytes.copy_from_slice(&bytes[..80]);
+        let (tree, len) = PartialMerkleTree::parse(&bytes[80..])?;
+        if 80 + len != bytes.len() {
+            return Err("Trailing bytes after the proof");
+        }
+        Ok(TxOutProof {
+            block_header: BlockHeader::from_bytes(header_bytes),
+            tree,
+        })
+    }
+
+    /// The matched txids, checked against the merkle root of the header of the proof
+    pub fn matches(&self) -> Result<Matches, &'static str> {
+        let matches = self.tree.extract()?;
+        match matches.root == self.block_header.merkle_root {
+            true => Ok(matches),
+            false => Err("Merkle root not matching the header"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use bitcoin::header::BlockHeader;
+    use bitcoin::merkle;
+    use bitcoin::merkle::{PartialMerkleTree, TxOutProof};
+
+    /// Partial tree of `txids` matching the one at `index`, as built by bitcoind
+    pub fn tree(txids: &[[u8; 32]], index: u32) -> PartialMerkleTree {
+        fn hash(tree: &PartialMerkleTree, txids: &[[u8; 32]], height: u32, pos: u32) -> [u8; 32] {
+            if height == 0 {
+                return txids[pos as usize];
+            }
+            let left = hash(tree, txids, height - 1, pos * 2);
+            match pos * 2 + 1 < tree.width(height - 1) {
+                true => merkle::parent(&left, &hash(tree, txids, height - 1, pos * 2 + 1)),
+                false => merkle::parent(&left, &left),
+            }
+        }
+        fn build(tree: &mut PartialMerkleTree, txids: &[[u8; 32]], index: u32, height: u32, pos: u32) {
+            let start = (pos << height) as usize;
+            let end = txids.len().min(((pos + 1) << height) as usize);
+            let parent_of_match = start <= index as usize && (index as usize) < end;
+            tree.bits.push(parent_of_match);
+            if height == 0 || !parent_of_match {
+                let hash = hash(tree, txids, height, pos);
+                tree.hashes.push(hash);
+            } else {
+                build(tree, txids, index, height - 1, pos * 2);
+                if (((pos * 2 + 1) << (height - 1)) as usize) < txids.len() {
+                    build(tree, txids, index, height - 1, pos * 2 + 1);
+                }
+            }
+        }
+        let mut tree = PartialMerkleTree {
+            transactions: txids.len() as u32,
+            hashes: Vec::new(),
+            bits: Vec::new(),
+        };
+        let mut height = 0;
+        while tree.width(height) > 1 {
+            height += 1;
+        }
+        build(&mut tree, txids, index, height, 0);
+        tree
+    }
+
+    /// `gettxoutproof` serialization of `tree` in the block of `block_header`
+    pub fn serialize(block_header: &BlockHeader, tree: &PartialMerkleTree) -> Vec<u8> {
+        let mut bytes = block_header.as_bytes().to_vec();
+        bytes.extend_from_slice(&tree.transactions.to_le_bytes());
+        bytes.push(tree.hashes.len() as u8);
+        for hash in tree.hashes.iter() {
+            bytes.extend_from_slice(hash);
+        }
+        let mut flags = vec![0u8; tree.bits.len().div_ceil(8)];
+        for (i, bit) in tree.bits.iter().enumerate() {
+            flags[i / 8] |= (*bit as u8) << (i % 8);
+        }
+        bytes.push(flags.len() as u8);
+        bytes.extend_from_slice(&flags);
+        bytes
+    }
+
+    #[test]
+    pub fn test_genesis_proof() {
+        // gettxoutproof of the genesis coinbase
+        let genesis = BlockHeader::genesis();
+        let mut bytes = genesis.as_bytes().to_vec();
+        bytes.extend_from_slice(&[1, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&genesis.merkle_root);
+        bytes.extend_from_slice(&[1, 1]);
+        let proof = TxOutProof::parse(&bytes).unwrap();
+        let matches = proof.matches().unwrap();
+        assert_eq!(matches.txids, vec![(0, genesis.merkle_root)]);
+        assert_eq!(merkle::branch_root(&genesis.merkle_root, &[], 0), genesis.merkle_root);
+
+        bytes.push(0);
+        assert!(TxOutProof::parse(&bytes).is_err());
+        assert!(TxOutProof::parse(&bytes[..100]).is_err());
+    }
+
+    #[test]
+    pub fn test_partial_merkle_tree() {
+        let txids: Vec<[u8; 32]> = (0..5u8).map(|i| [i; 32]).collect();
+        let expected = merkle::root(&txids);
+        // branch of the transaction 2: 3, then hash(0, 1), then hash(4, 4)
+        let branch = [
+            txids[3],
+            merkle::parent(&txids[0], &txids[1]),
+            merkle::parent(&merkle::parent(&txids[4], &txids[4]), &merkle::parent(&txids[4], &txids[4])),
+        ];
+        assert_eq!(merkle::branch_root(&txids[2], &branch, 2), expected);
+        assert_ne!(merkle::branch_root(&txids[2], &branch, 3), expected);
+        for index in 0..5 {
+            let branch = merkle::branch(&txids, index);
+            assert_eq!(merkle::branch_root(&txids[index], &branch, index as u32), expected);
+        }
+        assert_eq!(merkle::branch(&txids, 2), branch.to_vec());
+
+        for index in 0..5 {
+            let tree = tree(&txids, index);
+            let matches = tree.extract().unwrap();
+            assert_eq!(matches.root, expected);
+            assert_eq!(matches.txids, vec![(index, txids[index as usize])]);
+
+            let mut block_header = BlockHeader::genesis();
+            block_header.merkle_root = expected;
+            let bytes = serialize(&block_header, &tree);
+            assert!(TxOutProof::parse(&bytes).unwrap().matches().is_ok());
+            block_header.merkle_root = txids[0];
+            let bytes = serialize(&block_header, &tree);
+            assert!(TxOutProof::parse(&bytes).unwrap().matches().is_err());
+        }
+
+        let mut tampered = tree(&txids, 4);
+        tampered.hashes.push([0; 32]);
+        assert!(tampered.extract().is_err());
+        let mut tampered = tree(&txids, 4);
+        tampered.transactions = 0;
+        assert!(tampered.extract().is_err());
+
+        // a duplicated last transaction, making an odd level even, is rejected
+        let mut duplicated = txids.clone();
+        duplicated.push(txids[4]);
+        duplicated.push(txids[4]);
+        duplicated.push(txids[4]);
+        assert_eq!(merkle::root(&duplicated), expected);
+        assert!(tree(&duplicated, 4).extract().is_err());
+    }
+}
@@ -1,30 +1,150 @@
 pub mod rpc;
+pub mod rest;
+pub mod p2p;
+pub mod zmq;
+pub mod hash;
 pub mod header;
+pub mod stream;
+pub mod stream_v2;
+pub mod trailer;
+pub mod blk;
+pub mod source;
+pub mod checkpoint;
+pub mod envelope;
+pub mod merkle;
 
 use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::path::{Path, PathBuf};
+use bitcoin::checkpoint::Checkpoints;
+use bitcoin::stream::ChunkSize;
+use util::hex::FromHex;
 
 
+/// The node interface used to fetch the headers
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Source {
+    /// JSON-RPC `getblockheader`, needs rpcuser and rpcpassword
+    Rpc,
+    /// Unauthenticated binary REST `/rest/headers`, needs `rest=1` on the node
+    Rest,
+    /// P2P protocol `getheaders`, any reachable peer will do
+    P2p,
+    /// The `blk*.dat` files of the node `blocks` directory, the node may not be running
+    Files,
+}
+
+impl Source {
+    fn from_name(value: &str) -> Option<Source> {
+        match value {
+            "rpc" => Some(Source::Rpc),
+            "rest" => Some(Source::Rest),
+            "p2p" => Some(Source::P2p),
+            "files" => Some(Source::Files),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Config {
     host : String,
     username : String,
     password : Option<String>,
+    source : Source,
+    peer : String,
+    zmq : Option<String>,
+    blocks : Option<PathBuf>,
+    store : Option<PathBuf>,
+    mmap : bool,
+    network : String,
+    checkpoints : Vec<(usize, [u8; 32])>,
+    trust_checkpoints : bool,
+    chunk_size : ChunkSize,
+    signing_key : Option<[u8; 32]>,
 }
 
 impl Config {
 
-    fn new (host: String, username: String, password: Option<String>) -> Config {
+    fn new() -> Config {
         Config {
-            host,
-            username,
-            password,
+            host: String::from("http://localhost:8332"),
+            username: String::new(),
+            password: None,
+            source: Source::Rpc,
+            peer: String::from("127.0.0.1:8333"),
+            zmq: None,
+            blocks: None,
+            store: None,
+            mmap: false,
+            network: String::from("main"),
+            checkpoints: Vec::new(),
+            trust_checkpoints: false,
+            chunk_size: stream::DEFAULT,
+            signing_key: None,
         }
     }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    pub fn zmq(&self) -> Option<&str> {
+        self.zmq.as_deref()
+    }
+
+    /// The `blocks` directory containing the `blk*.dat` files
+    pub fn blocks(&self) -> Option<&Path> {
+        self.blocks.as_deref()
+    }
+
+    /// The file persisting the compressed stream, kept in memory if missing
+    pub fn store(&self) -> Option<&Path> {
+        self.store.as_deref()
+    }
+
+    /// Whether `store` is a directory of memory mapped chunk files
+    pub fn mmap(&self) -> bool {
+        self.mmap
+    }
+
+    /// The built-in checkpoints of the network plus the configured ones
+    pub fn checkpoints(&self) -> Checkpoints {
+        let mut checkpoints = Checkpoints::defaults(&self.network);
+        for &(height, hash) in self.checkpoints.iter() {
+            checkpoints.add(height, hash);
+        }
+        checkpoints
+    }
+
+    /// Chunk size of the served stream
+    pub fn chunk_size(&self) -> ChunkSize {
+        self.chunk_size
+    }
+
+    /// `main`, the only network supported, see `envelope::network_magic`
+    pub fn network(&self) -> &str {
+        &self.network
+    }
+
+    /// Whether the headers up to the last checkpoint are accepted without verifying them
+    pub fn trust_checkpoints(&self) -> bool {
+        self.trust_checkpoints
+    }
+
+    /// Seed of the ed25519 key signing the served tips
+    pub fn signing_key(&self) -> Option<&[u8; 32]> {
+        self.signing_key.as_ref()
+    }
+
     pub fn read() -> Result<Config, &'static str> {
-        let mut host: Option<String> = Some(String::from("http://localhost:8332"));
+        let mut config = Config::new();
         let mut username: Option<String> = None;
-        let mut password: Option<String> = None;
 
         match env::home_dir() {
             Some(path) => {
@@ -35,37 +155,94 @@ impl Config {
                 ];
                 for filename in paths {
                     let full_path = format!("{}{}", path.display(), filename);
-                    let f = File::open(full_path.clone());
-                    match f {
-                        Ok(mut f) => {
-                            let mut contents = String::new();
-                            f.read_to_string(&mut contents)
-                                .expect("something went wrong reading the file");
-                            println!("Found config file at {}", full_path);
-                            let x = contents.split("\n");
-                            for el in x {
-                                let x = el.replace(" ", "");
-                                if x.starts_with("rpcuser=") {
-                                    username = Some(String::from(&x[8..]));
+                    if let Ok(mut f) = File::open(full_path.clone()) {
+                        let mut contents = String::new();
+                        f.read_to_string(&mut contents)
+                            .expect("something went wrong reading the file");
+                        println!("Found config file at {}", full_path);
+                        let conf_dir = Path::new(&full_path).parent().unwrap();
+                        let mut datadir: Option<PathBuf> = None;
+                        let mut blocksdir: Option<PathBuf> = None;
+                        let x = contents.split('\n');
+                        for el in x {
+                            let x = el.replace(" ", "");
+                            if let Some(value) = x.strip_prefix("rpcuser=") {
+                                username = Some(String::from(value));
+                            }
+                            if let Some(value) = x.strip_prefix("rpcpassword=") {
+                                config.password = Some(String::from(value));
+                            }
+                            if let Some(value) = x.strip_prefix("rpchost=") {
+                                config.host = String::from(value);
+                            }
+                            if let Some(value) = x.strip_prefix("headerssource=") {
+                                match Source::from_name(value) {
+                                    Some(s) => config.source = s,
+                                    None => return Err("Unknown headerssource, use rpc, rest, p2p or files"),
                                 }
-                                if x.starts_with("rpcpassword=") {
-                                    password = Some(String::from(&x[12..]));
+                            }
+                            if let Some(value) = x.strip_prefix("headerspeer=") {
+                                config.peer = String::from(value);
+                            }
+                            if let Some(value) = x.strip_prefix("zmqpubhashblock=") {
+                                config.zmq = Some(String::from(value));
+                            }
+                            if let Some(value) = x.strip_prefix("headersstore=") {
+                                config.store = Some(PathBuf::from(value));
+                            }
+                            if let Some(value) = x.strip_prefix("headersmmap=") {
+                                config.mmap = value == "1";
+                            }
+                            if x == "testnet=1" || x == "regtest=1" {
+                                return Err("Only mainnet is supported, remove testnet=1 and regtest=1");
+                            }
+                            if let Some(value) = x.strip_prefix("headerscheckpoint=") {
+                                match Checkpoints::parse(value) {
+                                    Some(checkpoint) => config.checkpoints.push(checkpoint),
+                                    None => return Err("Invalid headerscheckpoint, use height:hash"),
                                 }
-                                if x.starts_with("rpchost=") {
-                                    host = Some(String::from(&x[8..]));
+                            }
+                            if let Some(value) = x.strip_prefix("headerschunksize=") {
+                                match value.parse().map(ChunkSize::new) {
+                                    Ok(Ok(chunk_size)) => config.chunk_size = chunk_size,
+                                    _ => return Err("Invalid headerschunksize, it must divide 2016"),
                                 }
                             }
+                            if let Some(value) = x.strip_prefix("headerstrustcheckpoints=") {
+                                config.trust_checkpoints = value == "1";
+                            }
+                            if let Some(value) = x.strip_prefix("headerssigningkey=") {
+                                match value.from_hex() {
+                                    Ok(ref seed) if seed.len() == 32 => {
+                                        let mut signing_key = [0u8; 32];
+                                        signing_key.copy_from_slice(seed);
+                                        config.signing_key = Some(signing_key);
+                                    }
+                                    _ => return Err("Invalid headerssigningkey, use 32 bytes in hex"),
+                                }
+                            }
+                            if let Some(value) = x.strip_prefix("datadir=") {
+                                datadir = Some(PathBuf::from(value));
+                            }
+                            if let Some(value) = x.strip_prefix("blocksdir=") {
+                                blocksdir = Some(PathBuf::from(value));
+                            }
                         }
-                        Err(_) => (),
+                        let blocks_parent = blocksdir.or(datadir).unwrap_or_else(|| conf_dir.to_path_buf());
+                        config.blocks = Some(blocks_parent.join("blocks"));
                     }
                 }
             }
             None => println!("Impossible to get your home dir!"),
         }
 
-        match host.is_some() && username.is_some() {
-            true => Ok( Config::new(host.unwrap(), username.unwrap(), password)),
-            false => Err("Cannot find rpcuser and rpcpassword"),
+        match (username, config.source) {
+            (Some(username), _) => {
+                config.username = username;
+                Ok(config)
+            }
+            (None, Source::Rpc) => Err("Cannot find rpcuser and rpcpassword"),
+            (None, _) => Ok(config),
         }
     }
 }
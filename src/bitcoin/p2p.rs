@@ -0,0 +1,501 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use bitcoin::hash;
+use bitcoin::header;
+use bitcoin::header::BlockHeader;
+use bitcoin::source::{headers_from_slice, not_found, HeaderSource};
+use util::uint::Uint256;
+
+/// Mainnet message start bytes
+pub const MAGIC: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
+
+/// Maximum number of headers a peer returns in a single `headers` message
+pub const MAX_HEADERS: usize = 2000;
+
+const PROTOCOL_VERSION: u32 = 70015;
+const USER_AGENT: &str = "/compressedheaders:0.1.0/";
+const MAX_PAYLOAD: usize = 32 * 1024 * 1024;
+
+pub struct Message {
+    pub command: String,
+    pub payload: Vec<u8>,
+}
+
+/// A connection to a bitcoin node which completed the version handshake
+pub struct Peer {
+    stream: TcpStream,
+}
+
+impl Peer {
+    pub fn connect(addr: &str) -> io::Result<Peer> {
+        let socket_addr = match addr.to_socket_addrs()?.next() {
+            Some(socket_addr) => socket_addr,
+            None => return Err(invalid_data(format!("cannot resolve {}", addr))),
+        };
+        let stream = TcpStream::connect_timeout(&socket_addr, Duration::from_secs(10))?;
+        stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+        let mut peer = Peer { stream };
+        peer.handshake()?;
+        Ok(peer)
+    }
+
+    fn handshake(&mut self) -> io::Result<()> {
+        write_message(&mut self.stream, "version", &version_payload())?;
+        let mut version_received = false;
+        let mut verack_received = false;
+        while !(version_received && verack_received) {
+            let message = read_message(&mut self.stream)?;
+            match message.command.as_str() {
+                "version" => {
+                    version_received = true;
+                    write_message(&mut self.stream, "verack", &[])?;
+                }
+                "verack" => verack_received = true,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Ask the headers following the first locator hash known by the peer,
+    /// answering pings and ignoring unrelated messages while waiting
+    pub fn get_headers(&mut self, locator: &[[u8; 32]]) -> io::Result<Vec<BlockHeader>> {
+        write_message(&mut self.stream, "getheaders", &getheaders_payload(locator))?;
+        loop {
+            let message = read_message(&mut self.stream)?;
+            match message.command.as_str() {
+                "headers" => return parse_headers_payload(&message.payload),
+                "ping" => write_message(&mut self.stream, "pong", &message.payload)?,
+                _ => (),
+            }
+        }
+    }
+}
+
+/// P2P source, keeping in memory the chain announced by the peer. The peer isn't trusted:
+/// the headers must link, have the bits and proof of work required on mainnet and a fork
+/// replaces the chain only if it has more work
+pub struct P2pSource {
+    addr: String,
+    peer: Option<Peer>,
+    block_headers: Vec<BlockHeader>,
+    block_hashes: Vec<[u8; 32]>,
+    /// Total work of the chain up to every height
+    chainwork: Vec<Uint256>,
+}
+
+impl P2pSource {
+    pub fn new(addr: &str) -> P2pSource {
+        let genesis = BlockHeader::genesis();
+        P2pSource {
+            addr: String::from(addr),
+            peer: None,
+            block_headers: vec![genesis],
+            block_hashes: vec![genesis.hash()],
+            chainwork: vec![genesis.work()],
+        }
+    }
+
+    /// Ask the peer for new headers until it has no more, reconnecting if needed.
+    /// The peer is disconnected if it sends invalid headers
+    fn update(&mut self) -> io::Result<()> {
+        loop {
+            if self.peer.is_none() {
+                self.peer = Some(Peer::connect(&self.addr)?);
+                println!("Connected to peer {}", self.addr);
+            }
+            let locator = locator(&self.block_hashes);
+            let received = self.peer.as_mut().unwrap().get_headers(&locator);
+            let fetched = match received.and_then(|received| self.connect(&received).map(|_| received.len())) {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    self.peer = None;
+                    return Err(e);
+                }
+            };
+            if fetched < MAX_HEADERS {
+                return Ok(());
+            }
+            println!("Received headers up to #{}", self.block_headers.len() - 1);
+        }
+    }
+
+    /// Check the headers `received` from the peer and add them to the chain, replacing the
+    /// headers after the one they follow if they have more work
+    fn connect(&mut self, received: &[BlockHeader]) -> io::Result<()> {
+        let first = match received.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        let fork_height = match self.block_hashes.iter().rposition(|h| *h == first.prev_blockhash) {
+            Some(fork_height) => fork_height,
+            None => return Err(invalid_data(String::from("peer sent headers not connecting to our chain"))),
+        };
+        let hashes = hash::headers(received);
+        let header_at = |height: usize| match height.checked_sub(fork_height + 1) {
+            Some(i) => &received[i],
+            None => &self.block_headers[height],
+        };
+        let mut chainwork = self.chainwork[fork_height];
+        for (i, (block_header, hash)) in received.iter().zip(&hashes).enumerate() {
+            let height = fork_height + 1 + i;
+            let prev_hash = match i {
+                0 => &self.block_hashes[fork_height],
+                i => &hashes[i - 1],
+            };
+            let period_first = height.checked_sub(header::DIFFICULTY_INTERVAL).map(header_at);
+            if let Err(e) = block_header.check_next(hash, height, header_at(height - 1), prev_hash, period_first) {
+                return Err(invalid_data(format!("peer sent header at height {} that {}", height, e)));
+            }
+            chainwork = chainwork + block_header.work();
+        }
+        if chainwork <= self.chainwork[self.chainwork.len() - 1] {
+            return Err(invalid_data(String::from("peer sent a fork without more work")));
+        }
+        self.block_headers.truncate(fork_height + 1);
+        self.block_hashes.truncate(fork_height + 1);
+        self.chainwork.truncate(fork_height + 1);
+        for (block_header, hash) in received.iter().zip(hashes) {
+            let chainwork = self.chainwork[self.chainwork.len() - 1] + block_header.work();
+            self.block_headers.push(*block_header);
+            self.block_hashes.push(hash);
+            self.chainwork.push(chainwork);
+        }
+        Ok(())
+    }
+}
+
+impl HeaderSource for P2pSource {
+    fn tip(&mut self) -> io::Result<usize> {
+        self.update()?;
+        Ok(self.block_headers.len() - 1)
+    }
+
+    fn header_at(&mut self, height: usize) -> io::Result<BlockHeader> {
+        self.block_headers
+            .get(height)
+            .cloned()
+            .ok_or_else(|| not_found(height))
+    }
+
+    fn headers_from(&mut self, height: usize, count: usize) -> io::Result<Vec<BlockHeader>> {
+        headers_from_slice(&self.block_headers, height, count)
+    }
+}
+
+/// Build a block locator from the hashes of the chain indexed by height:
+/// the last 10 hashes one by one, then doubling the step back to genesis
+pub fn locator(block_hashes: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    let mut result = Vec::new();
+    if block_hashes.is_empty() {
+        return result;
+    }
+    let mut height = block_hashes.len() - 1;
+    let mut step = 1;
+    loop {
+        result.push(block_hashes[height]);
+        if height == 0 {
+            break;
+        }
+        if result.len() >= 10 {
+            step *= 2;
+        }
+        height = height.saturating_sub(step);
+    }
+    result
+}
+
+pub fn read_message<R: Read>(reader: &mut R) -> io::Result<Message> {
+    let mut header: [u8; 24] = [0; 24];
+    reader.read_exact(&mut header)?;
+    if header[0..4] != MAGIC {
+        return Err(invalid_data(String::from("wrong network magic")));
+    }
+    let command: String = header[4..16]
+        .iter()
+        .take_while(|b| **b != 0)
+        .map(|b| *b as char)
+        .collect();
+    let length = read_u32(&header[16..20]) as usize;
+    if length > MAX_PAYLOAD {
+        return Err(invalid_data(format!("payload of {} bytes too big", length)));
+    }
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload)?;
+    if header[20..24] != checksum(&payload) {
+        return Err(invalid_data(format!("wrong checksum for {}", command)));
+    }
+    Ok(Message { command, payload })
+}
+
+pub fn write_message<W: Write>(writer: &mut W, command: &str, payload: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(24 + payload.len());
+    message.extend_from_slice(&MAGIC);
+    let mut command_bytes: [u8; 12] = [0; 12];
+    command_bytes[..command.len()].clone_from_slice(command.as_bytes());
+    message.extend_from_slice(&command_bytes);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(&checksum(payload));
+    message.extend_from_slice(payload);
+    writer.write_all(&message)?;
+    writer.flush()
+}
+
+pub fn getheaders_payload(locator: &[[u8; 32]]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(4 + 9 + 32 * (locator.len() + 1));
+    payload.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    write_var_int(&mut payload, locator.len() as u64);
+    for hash in locator {
+        payload.extend_from_slice(hash);
+    }
+    payload.extend_from_slice(&[0u8; 32]); // hash_stop, as many as possible
+    payload
+}
+
+/// Parse a `getheaders` payload returning the locator hashes
+pub fn parse_getheaders_payload(payload: &[u8]) -> io::Result<Vec<[u8; 32]>> {
+    if payload.len() < 4 {
+        return Err(invalid_data(String::from("getheaders too short")));
+    }
+    let (count, mut pos) = read_var_int(&payload[4..])?;
+    pos += 4;
+    let mut result = Vec::new();
+    for _ in 0..count {
+        if payload.len() < pos + 32 {
+            return Err(invalid_data(String::from("getheaders truncated")));
+        }
+        let mut hash: [u8; 32] = [0; 32];
+        hash.clone_from_slice(&payload[pos..pos + 32]);
+        result.push(hash);
+        pos += 32;
+    }
+    Ok(result)
+}
+
+pub fn headers_payload(block_headers: &[BlockHeader]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(9 + 81 * block_headers.len());
+    write_var_int(&mut payload, block_headers.len() as u64);
+    for block_header in block_headers {
+        payload.extend_from_slice(&block_header.as_bytes());
+        payload.push(0); // transaction count, always zero
+    }
+    payload
+}
+
+pub fn parse_headers_payload(payload: &[u8]) -> io::Result<Vec<BlockHeader>> {
+    let (count, mut pos) = read_var_int(payload)?;
+    if count as usize > MAX_HEADERS {
+        return Err(invalid_data(format!("too many headers {}", count)));
+    }
+    let mut result = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        if payload.len() < pos + 81 {
+            return Err(invalid_data(String::from("headers truncated")));
+        }
+        let mut header_bytes: [u8; 80] = [0; 80];
+        header_bytes.clone_from_slice(&payload[pos..pos + 80]);
+        result.push(BlockHeader::from_bytes(header_bytes));
+        pos += 81;
+    }
+    Ok(result)
+}
+
+fn version_payload() -> Vec<u8> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut payload = Vec::with_capacity(86 + USER_AGENT.len());
+    payload.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    payload.extend_from_slice(&0u64.to_le_bytes()); // services
+    payload.extend_from_slice(&timestamp.to_le_bytes());
+    payload.extend_from_slice(&[0u8; 26]); // addr_recv
+    payload.extend_from_slice(&[0u8; 26]); // addr_from
+    payload.extend_from_slice(&timestamp.to_le_bytes()); // nonce
+    write_var_int(&mut payload, USER_AGENT.len() as u64);
+    payload.extend_from_slice(USER_AGENT.as_bytes());
+    payload.extend_from_slice(&0u32.to_le_bytes()); // start_height
+    payload.push(0); // relay
+    payload
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = hash::sha256d(payload);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn write_var_int(vec: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => vec.push(value as u8),
+        0xfd..=0xffff => {
+            vec.push(0xfd);
+            vec.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            vec.push(0xfe);
+            vec.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        _ => {
+            vec.push(0xff);
+            vec.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Returns the value and the number of bytes consumed
+pub fn read_var_int(bytes: &[u8]) -> io::Result<(u64, usize)> {
+    let size = match bytes.first() {
+        Some(&0xfd) => 3,
+        Some(&0xfe) => 5,
+        Some(&0xff) => 9,
+        Some(_) => 1,
+        None => return Err(invalid_data(String::from("missing var int"))),
+    };
+    if bytes.len() < size {
+        return Err(invalid_data(String::from("var int truncated")));
+    }
+    let value = match size {
+        1 => u64::from(bytes[0]),
+        _ => {
+            let mut le: [u8; 8] = [0; 8];
+            le[..size - 1].clone_from_slice(&bytes[1..size]);
+            u64::from_le_bytes(le)
+        }
+    };
+    Ok((value, size))
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut le: [u8; 4] = [0; 4];
+    le.clone_from_slice(bytes);
+    u32::from_le_bytes(le)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::header::BlockHeader;
+    use bitcoin::p2p;
+    use bitcoin::p2p::{P2pSource, Peer};
+    use bitcoin::source::HeaderSource;
+    use bitcoin::stream;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use util::hex::ToHex;
+
+    /// Stand-in peer replaying the fixture headers to a single connection
+    fn serve(mut stream: TcpStream, block_headers: Vec<BlockHeader>) {
+        let hashes: Vec<[u8; 32]> = block_headers.iter().map(|h| h.hash()).collect();
+        loop {
+            let message = match p2p::read_message(&mut stream) {
+                Ok(message) => message,
+                Err(_) => return,
+            };
+            match message.command.as_str() {
+                "version" => {
+                    p2p::write_message(&mut stream, "version", &p2p::version_payload()).unwrap();
+                    p2p::write_message(&mut stream, "verack", &[]).unwrap();
+                }
+                "getheaders" => {
+                    let locator = p2p::parse_getheaders_payload(&message.payload).unwrap();
+                    let start = locator
+                        .iter()
+                        .filter_map(|l| hashes.iter().position(|h| h == l))
+                        .next()
+                        .map(|p| p + 1)
+                        .unwrap_or(0);
+                    let end = (start + p2p::MAX_HEADERS).min(block_headers.len());
+                    p2p::write_message(&mut stream, "ping", &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+                    let payload = p2p::headers_payload(&block_headers[start..end]);
+                    p2p::write_message(&mut stream, "headers", &payload).unwrap();
+                }
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_sync_from_stand_in_peer() {
+        let block_headers = stream::decode(include_bytes!("../../examples/2016/0")).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let served = block_headers.clone();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, served);
+        });
+
+        let mut peer = Peer::connect(&addr).unwrap();
+        let mut hashes = vec![block_headers[0].hash()];
+        loop {
+            let received = peer.get_headers(&p2p::locator(&hashes)).unwrap();
+            if received.is_empty() {
+                break;
+            }
+            for block_header in received {
+                assert_eq!(&block_header.prev_blockhash, hashes.last().unwrap());
+                hashes.push(block_header.hash());
+            }
+        }
+        assert_eq!(hashes.len(), 2016);
+        assert_eq!(
+            hashes[2015].to_hex(),
+            "6397bb6abd4fc521c0d3f6071b5650389f0b4551bc40b4e6b067306900000000"
+        );
+    }
+
+    #[test]
+    pub fn test_p2p_source_checks_headers() {
+        let block_headers = stream::decode(include_bytes!("../../examples/2016/0")).unwrap();
+        let mut tampered = block_headers.clone();
+        tampered[1500].nonce = [0; 4];
+        for height in 1501..tampered.len() {
+            tampered[height].prev_blockhash = tampered[height - 1].hash();
+        }
+        for (served, valid) in [(block_headers, true), (tampered, false)] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            let tip_hash = served[2015].hash();
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                serve(stream, served);
+            });
+            let mut source = P2pSource::new(&addr);
+            match valid {
+                true => {
+                    let tip = source.tip().unwrap();
+                    assert_eq!(source.header_at(tip).unwrap().hash(), tip_hash);
+                }
+                false => {
+                    // the whole batch with the header without proof of work is discarded
+                    assert!(source.tip().is_err());
+                    assert!(source.peer.is_none());
+                    assert_eq!(source.block_headers.len(), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_locator() {
+        let hashes: Vec<[u8; 32]> = (0..100u8).map(|i| [i; 32]).collect();
+        let heights: Vec<u8> = p2p::locator(&hashes).iter().map(|h| h[0]).collect();
+        assert_eq!(heights, vec![99, 98, 97, 96, 95, 94, 93, 92, 91, 90, 88, 84, 76, 60, 28, 0]);
+        assert!(p2p::locator(&[]).is_empty());
+    }
+
+    #[test]
+    pub fn test_var_int() {
+        for value in &[0u64, 0xfc, 0xfd, 0xffff, 0x10000, 0xffff_ffff, 0x1_0000_0000] {
+            let mut vec = Vec::new();
+            p2p::write_var_int(&mut vec, *value);
+            assert_eq!(p2p::read_var_int(&vec).unwrap(), (*value, vec.len()));
+        }
+    }
+}
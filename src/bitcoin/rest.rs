@@ -0,0 +1,150 @@
+use hyper::{Client, Error, StatusCode, Uri};
+use tokio_core::reactor::Core;
+use futures::{Future, Stream};
+use serde_json;
+use std::io;
+use bitcoin::Config;
+use bitcoin::header::BlockHeader;
+use bitcoin::rpc::to_io_error;
+use bitcoin::source::{not_found, HeaderSource};
+use util::hex::ToHex;
+
+/// Maximum number of headers bitcoind returns for a single REST request
+pub const MAX_HEADERS: usize = 2000;
+
+pub fn get_headers(
+    count: usize,
+    block_hash: &str,
+    config: &Config,
+) -> Result<Vec<BlockHeader>, Error> {
+    let body = get(&format!("headers/{}/{}.bin", count, block_hash), config)?;
+    match parse_headers(&body) {
+        Some(block_headers) => Ok(block_headers),
+        None => Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("rest response of {} bytes is not a multiple of 80", body.len()),
+        ))),
+    }
+}
+
+/// Hash of the block at `height` in the best chain, as shown by the node (big endian hex)
+pub fn get_block_hash_by_height(height: usize, config: &Config) -> Result<String, Error> {
+    let body = get(&format!("blockhashbyheight/{}.bin", height), config)?;
+    if body.len() != 32 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("rest block hash of {} bytes", body.len()),
+        )));
+    }
+    let mut hash = body;
+    hash.reverse();
+    Ok(hash.to_hex())
+}
+
+/// Number of blocks in the best chain of the node
+pub fn get_blocks(config: &Config) -> Result<usize, Error> {
+    let body = get("chaininfo.json", config)?;
+    let value: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(value) => value,
+        Err(e) => return Err(Error::Io(e.into())),
+    };
+    match value["blocks"].as_u64() {
+        Some(blocks) => Ok(blocks as usize),
+        None => Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "rest chaininfo without blocks",
+        ))),
+    }
+}
+
+fn get(path: &str, config: &Config) -> Result<Vec<u8>, Error> {
+    let mut core = Core::new()?;
+    let client = Client::new(&core.handle());
+    let uri: Uri = match format!("{}/rest/{}", config.host, path).parse() {
+        Ok(uri) => uri,
+        Err(e) => return Err(Error::Uri(e)),
+    };
+
+    let work = client.get(uri).and_then(|res| {
+        let status = res.status();
+        res.body().concat2().map(move |body| (status, body))
+    });
+
+    let (status, body) = core.run(work)?;
+    if status != StatusCode::Ok {
+        return Err(Error::Io(io::Error::other(format!(
+            "rest request failed with status {}",
+            status
+        ))));
+    }
+    Ok(body.to_vec())
+}
+
+/// REST source, asking up to `MAX_HEADERS` headers per request
+pub struct RestSource {
+    config: Config,
+}
+
+impl RestSource {
+    pub fn new(config: Config) -> RestSource {
+        RestSource { config }
+    }
+}
+
+impl HeaderSource for RestSource {
+    fn tip(&mut self) -> io::Result<usize> {
+        get_blocks(&self.config).map_err(to_io_error)
+    }
+
+    fn header_at(&mut self, height: usize) -> io::Result<BlockHeader> {
+        match self.headers_from(height, 1)?.pop() {
+            Some(block_header) => Ok(block_header),
+            None => Err(not_found(height)),
+        }
+    }
+
+    fn headers_from(&mut self, height: usize, count: usize) -> io::Result<Vec<BlockHeader>> {
+        let block_hash = get_block_hash_by_height(height, &self.config).map_err(to_io_error)?;
+        get_headers(count.min(MAX_HEADERS), &block_hash, &self.config).map_err(to_io_error)
+    }
+}
+
+/// Split the concatenated 80 bytes headers returned by the `.bin` format
+pub fn parse_headers(bytes: &[u8]) -> Option<Vec<BlockHeader>> {
+    if !bytes.len().is_multiple_of(80) {
+        return None;
+    }
+    let block_headers = bytes
+        .chunks(80)
+        .map(|chunk| {
+            let mut header_bytes: [u8; 80] = [0; 80];
+            header_bytes.clone_from_slice(chunk);
+            BlockHeader::from_bytes(header_bytes)
+        })
+        .collect();
+    Some(block_headers)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::header::BlockHeader;
+    use bitcoin::rest::parse_headers;
+    use util::hex::ToHex;
+
+    #[test]
+    pub fn test_parse_headers() {
+        let genesis = BlockHeader::genesis();
+        let mut bytes = genesis.as_bytes().to_vec();
+        bytes.extend_from_slice(&genesis.as_bytes());
+        let block_headers = parse_headers(&bytes).unwrap();
+        assert_eq!(block_headers.len(), 2);
+        assert_eq!(
+            block_headers[1].hash_be().to_hex(),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+
+        assert!(parse_headers(&bytes[..100]).is_none());
+        assert_eq!(parse_headers(&[]).unwrap().len(), 0);
+    }
+}
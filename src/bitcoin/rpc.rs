@@ -2,9 +2,26 @@ use hyper::{Body, Client, Error, Method, Request};
 use hyper::header::{Authorization, Basic};
 use tokio_core::reactor::Core;
 use futures::{Future, Stream};
+use serde::de::DeserializeOwned;
 use serde_json;
+use std::io;
 use std::str;
 use bitcoin::Config;
+use bitcoin::header::BlockHeader;
+use bitcoin::source::HeaderSource;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcResponse<T> {
+    pub result: Option<T>,
+    pub id: String,
+    pub error: Option<RpcError>,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BlockHeaderRpcResponse {
@@ -35,6 +52,24 @@ pub fn get_block_header(
     block_hash: String,
     config: &Config,
 ) -> Result<BlockHeaderRpcResponse, Error> {
+    let result = call("getblockheader", &format!("\"{}\"", block_hash), config)?;
+    Ok(BlockHeaderRpcResponse {
+        result,
+        id: String::from("0"),
+        error: None,
+    })
+}
+
+pub fn get_block_hash(height: usize, config: &Config) -> Result<String, Error> {
+    call("getblockhash", &format!("{}", height), config)
+}
+
+pub fn get_block_count(config: &Config) -> Result<usize, Error> {
+    call("getblockcount", "", config)
+}
+
+/// Call `method` with the already JSON encoded, comma separated, `params`
+pub fn call<T: DeserializeOwned>(method: &str, params: &str, config: &Config) -> Result<T, Error> {
     let auth = Authorization(Basic {
         username: config.username.clone(),
         password: config.password.clone(),
@@ -42,10 +77,10 @@ pub fn get_block_header(
     let mut core = Core::new()?;
     let client = Client::new(&core.handle());
     let request_body_string: String = format!(
-        "{{\"jsonrpc\":\"1.0\",\"id\":\"{}\",\"method\":\"{}\",\"params\":[\"{}\"]}}",
+        "{{\"jsonrpc\":\"1.0\",\"id\":\"{}\",\"method\":\"{}\",\"params\":[{}]}}",
         0,
-        "getblockheader",
-        block_hash
+        method,
+        params
     );
     let mut req: Request = Request::new(Method::Post, config.host.parse().unwrap());
     req.set_body(Body::from(request_body_string));
@@ -65,12 +100,64 @@ pub fn get_block_header(
     let utf8 = str::from_utf8(&work_result)?;
 
     //println!("GET: {}", utf8);
-    let block_header_rpc_response: BlockHeaderRpcResponse = match serde_json::from_str(utf8) {
+    let rpc_response: RpcResponse<T> = match serde_json::from_str(utf8) {
         Err(e) => return Err(Error::Io(e.into())),
         Ok(f) => f,
     };
 
-    //let block_header_rpc_response = serde_json::from_str(utf8)?;
+    match (rpc_response.result, rpc_response.error) {
+        (Some(result), _) => Ok(result),
+        (None, Some(e)) => Err(Error::Io(io::Error::other(format!(
+            "{} failed with code {}: {}",
+            method, e.code, e.message
+        )))),
+        (None, None) => Err(Error::Io(io::Error::other(format!("{} returned null", method)))),
+    }
+}
+
+/// Convert the errors of the http based sources
+pub fn to_io_error(e: Error) -> io::Error {
+    match e {
+        Error::Io(e) => e,
+        e => io::Error::other(format!("{}", e)),
+    }
+}
+
+/// JSON-RPC source, following `nextblockhash` to get consecutive headers
+pub struct RpcSource {
+    config: Config,
+}
+
+impl RpcSource {
+    pub fn new(config: Config) -> RpcSource {
+        RpcSource { config }
+    }
+}
+
+impl HeaderSource for RpcSource {
+    fn tip(&mut self) -> io::Result<usize> {
+        get_block_count(&self.config).map_err(to_io_error)
+    }
+
+    fn header_at(&mut self, height: usize) -> io::Result<BlockHeader> {
+        let block_hash = get_block_hash(height, &self.config).map_err(to_io_error)?;
+        let response = get_block_header(block_hash, &self.config).map_err(to_io_error)?;
+        Ok(BlockHeader::from_block_header_rpc(response.result))
+    }
 
-    Ok(block_header_rpc_response)
+    fn headers_from(&mut self, height: usize, count: usize) -> io::Result<Vec<BlockHeader>> {
+        let mut result = Vec::with_capacity(count);
+        let mut block_hash = Some(get_block_hash(height, &self.config).map_err(to_io_error)?);
+        while let Some(hash) = block_hash {
+            if result.len() == count {
+                break;
+            }
+            let block_header_rpc = get_block_header(hash, &self.config)
+                .map_err(to_io_error)?
+                .result;
+            block_hash = block_header_rpc.nextblockhash.clone();
+            result.push(BlockHeader::from_block_header_rpc(block_header_rpc));
+        }
+        Ok(result)
+    }
 }
@@ -0,0 +1,85 @@
+use std::io;
+use bitcoin::header::BlockHeader;
+
+/// A backend providing the headers of the best chain by height
+pub trait HeaderSource {
+    /// Height of the best chain tip
+    fn tip(&mut self) -> io::Result<usize>;
+
+    /// The header at `height` in the best chain
+    fn header_at(&mut self, height: usize) -> io::Result<BlockHeader>;
+
+    /// Up to `count` consecutive headers of the best chain starting at `height`
+    fn headers_from(&mut self, height: usize, count: usize) -> io::Result<Vec<BlockHeader>>;
+}
+
+impl<S: HeaderSource + ?Sized> HeaderSource for Box<S> {
+    fn tip(&mut self) -> io::Result<usize> {
+        (**self).tip()
+    }
+
+    fn header_at(&mut self, height: usize) -> io::Result<BlockHeader> {
+        (**self).header_at(height)
+    }
+
+    fn headers_from(&mut self, height: usize, count: usize) -> io::Result<Vec<BlockHeader>> {
+        (**self).headers_from(height, count)
+    }
+}
+
+/// Headers kept in memory, useful to test the sync and reorg logic
+pub struct MemorySource {
+    block_headers: Vec<BlockHeader>,
+}
+
+impl MemorySource {
+    pub fn new(block_headers: Vec<BlockHeader>) -> MemorySource {
+        MemorySource { block_headers }
+    }
+
+    /// Replace the chain from `height` on, simulating new blocks or a reorg
+    pub fn replace_from(&mut self, height: usize, block_headers: Vec<BlockHeader>) {
+        self.block_headers.truncate(height);
+        self.block_headers.extend(block_headers);
+    }
+}
+
+impl HeaderSource for MemorySource {
+    fn tip(&mut self) -> io::Result<usize> {
+        match self.block_headers.len() {
+            0 => Err(not_found(0)),
+            len => Ok(len - 1),
+        }
+    }
+
+    fn header_at(&mut self, height: usize) -> io::Result<BlockHeader> {
+        self.block_headers
+            .get(height)
+            .cloned()
+            .ok_or_else(|| not_found(height))
+    }
+
+    fn headers_from(&mut self, height: usize, count: usize) -> io::Result<Vec<BlockHeader>> {
+        headers_from_slice(&self.block_headers, height, count)
+    }
+}
+
+/// `headers_from` implementation for sources keeping the whole chain
+pub fn headers_from_slice(
+    block_headers: &[BlockHeader],
+    height: usize,
+    count: usize,
+) -> io::Result<Vec<BlockHeader>> {
+    if height >= block_headers.len() {
+        return Err(not_found(height));
+    }
+    let end = block_headers.len().min(height + count);
+    Ok(block_headers[height..end].to_vec())
+}
+
+pub fn not_found(height: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no header at height {}", height),
+    )
+}
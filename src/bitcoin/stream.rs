@@ -0,0 +1,292 @@
+use bitcoin::header::BlockHeader;
+
+/// Number of headers in a chunk, the difficulty adjustment period
+pub const CHUNK_SIZE: usize = 2016;
+
+/// Number of headers in a chunk of a stream, a full header starts every chunk so
+/// the chunk size must divide the difficulty adjustment period, where the bits can change
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChunkSize(usize);
+
+/// The default chunk size, the difficulty adjustment period
+pub const DEFAULT: ChunkSize = ChunkSize(CHUNK_SIZE);
+
+impl ChunkSize {
+    pub fn new(size: usize) -> Result<ChunkSize, &'static str> {
+        match size > 0 && CHUNK_SIZE.is_multiple_of(size) {
+            true => Ok(ChunkSize(size)),
+            false => Err("Chunk size must divide 2016"),
+        }
+    }
+
+    pub fn get(self) -> usize {
+        self.0
+    }
+
+    /// Append the header at `height` to the compressed stream: full at the start of
+    /// every chunk, stripped of previous hash and bits otherwise
+    pub fn append(self, bytes: &mut Vec<u8>, height: usize, block_header: &BlockHeader) {
+        match self.is_full(height) {
+            true => bytes.extend_from_slice(&block_header.as_bytes()),
+            false => bytes.extend_from_slice(&block_header.as_compressed_bytes()),
+        }
+    }
+
+    /// Length in bytes of the stream containing `count` headers
+    pub fn byte_len(self, count: usize) -> usize {
+        let full = count.div_ceil(self.0);
+        full * 80 + (count - full) * 44
+    }
+
+    /// Byte offset in the stream of the header at `height`
+    pub fn offset(self, height: usize) -> usize {
+        self.byte_len(height)
+    }
+
+    /// Whether the header at `height` is stored in full, with previous hash and bits
+    pub fn is_full(self, height: usize) -> bool {
+        height.is_multiple_of(self.0)
+    }
+
+    /// Length in bytes of the header at `height`
+    pub fn header_len(self, height: usize) -> usize {
+        match self.is_full(height) {
+            true => 80,
+            false => 44,
+        }
+    }
+
+    /// Height of the header containing the byte at `offset` and whether it is a full header
+    pub fn height_at(self, offset: usize) -> (usize, bool) {
+        let chunk_len = self.byte_len(self.0);
+        let rest = offset % chunk_len;
+        let height = offset / chunk_len * self.0 + match rest {
+            0..=79 => 0,
+            _ => 1 + (rest - 80) / 44,
+        };
+        (height, self.is_full(height))
+    }
+
+    /// Length in bytes of the headers from `start` to `end` heights, end excluded
+    pub fn range_len(self, start: usize, end: usize) -> usize {
+        self.byte_len(end) - self.byte_len(start)
+    }
+
+    /// Number of complete headers contained in the first `len` bytes of the stream
+    pub fn count(self, len: usize) -> usize {
+        if len == 0 {
+            return 0;
+        }
+        let (height, _) = self.height_at(len - 1);
+        match len >= self.offset(height) + self.header_len(height) {
+            true => height + 1,
+            false => height,
+        }
+    }
+
+    /// Compressed stream of headers starting from height 0
+    pub fn encode(self, block_headers: &[BlockHeader]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (height, block_header) in block_headers.iter().enumerate() {
+            self.append(&mut bytes, height, block_header);
+        }
+        bytes
+    }
+
+    /// Rebuild the headers of the stream from the header at `height`, `prev` is the header
+    /// before it, needed to rebuild the first header when it's compressed
+    pub fn decode_from(
+        self,
+        bytes: &[u8],
+        height: usize,
+        prev: Option<&BlockHeader>,
+    ) -> Result<Vec<BlockHeader>, &'static str> {
+        let mut result: Vec<BlockHeader> = Vec::new();
+        let mut prev = prev.cloned();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let block_header = match self.is_full(height + result.len()) {
+                true => {
+                    if bytes.len() < pos + 80 {
+                        return Err("Stream truncated in a full header");
+                    }
+                    let mut header_bytes: [u8; 80] = [0; 80];
+                    header_bytes.clone_from_slice(&bytes[pos..pos + 80]);
+                    pos += 80;
+                    BlockHeader::from_bytes(header_bytes)
+                }
+                false => {
+                    if bytes.len() < pos + 44 {
+                        return Err("Stream truncated in a compressed header");
+                    }
+                    let mut compressed_bytes: [u8; 44] = [0; 44];
+                    compressed_bytes.clone_from_slice(&bytes[pos..pos + 44]);
+                    pos += 44;
+                    let prev = match prev {
+                        Some(ref prev) => prev,
+                        None => return Err("Missing the header before a compressed header"),
+                    };
+                    BlockHeader::from_compressed_bytes(compressed_bytes, prev.hash(), prev.bits)
+                }
+            };
+            prev = Some(block_header);
+            result.push(block_header);
+        }
+        Ok(result)
+    }
+}
+
+/// Append the header at `height` to the stream with the default chunk size
+pub fn append(bytes: &mut Vec<u8>, height: usize, block_header: &BlockHeader) {
+    DEFAULT.append(bytes, height, block_header)
+}
+
+/// Length in bytes of the stream containing `count` headers
+pub fn byte_len(count: usize) -> usize {
+    DEFAULT.byte_len(count)
+}
+
+/// Byte offset in the stream of the header at `height`
+pub fn offset(height: usize) -> usize {
+    DEFAULT.offset(height)
+}
+
+/// Whether the header at `height` is stored in full, with previous hash and bits
+pub fn is_full(height: usize) -> bool {
+    DEFAULT.is_full(height)
+}
+
+/// Length in bytes of the header at `height`
+pub fn header_len(height: usize) -> usize {
+    DEFAULT.header_len(height)
+}
+
+/// Height of the header containing the byte at `offset` and whether it is a full header
+pub fn height_at(offset: usize) -> (usize, bool) {
+    DEFAULT.height_at(offset)
+}
+
+/// Length in bytes of the headers from `start` to `end` heights, end excluded
+pub fn range_len(start: usize, end: usize) -> usize {
+    DEFAULT.range_len(start, end)
+}
+
+/// Number of complete headers contained in the first `len` bytes of the stream
+pub fn count(len: usize) -> usize {
+    DEFAULT.count(len)
+}
+
+/// Compressed stream of headers starting from height 0
+pub fn encode(block_headers: &[BlockHeader]) -> Vec<u8> {
+    DEFAULT.encode(block_headers)
+}
+
+/// Rebuild the headers of a compressed stream starting from height 0
+pub fn decode(bytes: &[u8]) -> Result<Vec<BlockHeader>, &'static str> {
+    DEFAULT.decode_from(bytes, 0, None)
+}
+
+/// Rebuild the headers of the stream from the header at `height`, `prev` is the header
+/// before it, needed to rebuild the first header when it's compressed
+pub fn decode_from(
+    bytes: &[u8],
+    height: usize,
+    prev: Option<&BlockHeader>,
+) -> Result<Vec<BlockHeader>, &'static str> {
+    DEFAULT.decode_from(bytes, height, prev)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::stream;
+    use bitcoin::stream::ChunkSize;
+    use util::hex::ToHex;
+
+    #[test]
+    pub fn test_decode_encode() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        assert_eq!(block_headers.len(), 2016);
+        assert_eq!(
+            block_headers[2015].hash().to_hex(),
+            "6397bb6abd4fc521c0d3f6071b5650389f0b4551bc40b4e6b067306900000000"
+        );
+        assert_eq!(stream::encode(&block_headers), test_data);
+
+        let start = stream::offset(1000);
+        let rest = stream::decode_from(&test_data[start..], 1000, Some(&block_headers[999])).unwrap();
+        assert_eq!(stream::encode(&rest)[80..], test_data[start + 44..]);
+        assert_eq!(rest[1015].hash(), block_headers[2015].hash());
+        assert!(stream::decode_from(&test_data[start..], 1000, None).is_err());
+
+        assert!(stream::decode(&test_data[..100]).is_err());
+        assert!(stream::decode(&test_data[..50]).is_err());
+    }
+
+    #[test]
+    pub fn test_offset() {
+        assert_eq!(stream::offset(1), 80);
+        assert_eq!(stream::offset(2016), 88740);
+        assert_eq!(stream::header_len(2016), 80);
+        assert_eq!(stream::header_len(2017), 44);
+        assert_eq!(stream::height_at(0), (0, true));
+        assert_eq!(stream::height_at(79), (0, true));
+        assert_eq!(stream::height_at(80), (1, false));
+        assert_eq!(stream::height_at(88739), (2015, false));
+        assert_eq!(stream::height_at(88740), (2016, true));
+        assert_eq!(stream::range_len(2015, 2017), 44 + 80);
+        for height in &[0, 1, 2015, 2016, 2017, 5000] {
+            let offset = stream::offset(*height);
+            assert_eq!(stream::height_at(offset).0, *height);
+            assert_eq!(stream::height_at(offset + stream::header_len(*height) - 1).0, *height);
+            assert_eq!(stream::range_len(*height, *height + 1), stream::header_len(*height));
+        }
+    }
+
+    #[test]
+    pub fn test_offset_layout() {
+        // a full header then compressed ones, in every chunk
+        for chunk in 0..3 {
+            let start = chunk * 2016;
+            assert_eq!(stream::offset(start), chunk * (80 + 2015 * 44));
+            for i in 0..2015 {
+                let height = start + i + 1;
+                assert_eq!(stream::offset(height), stream::offset(start) + 80 + 44 * i);
+                assert_eq!(stream::header_len(height), 44);
+                assert_eq!(stream::height_at(stream::offset(start) + 80 + 44 * i + 43), (height, false));
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_chunk_size() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        let chunk_size = ChunkSize::new(144).unwrap();
+        assert!(ChunkSize::new(100).is_err());
+        assert!(ChunkSize::new(0).is_err());
+
+        let bytes = chunk_size.encode(&block_headers);
+        assert_eq!(bytes.len(), 14 * 80 + (2016 - 14) * 44);
+        assert_eq!(&bytes[..chunk_size.byte_len(144)], &include_bytes!("../../examples/144/0")[..]);
+        assert_eq!(chunk_size.offset(145), 80 + 143 * 44 + 80);
+        assert_eq!(chunk_size.height_at(chunk_size.offset(288)), (288, true));
+        assert_eq!(chunk_size.count(bytes.len() - 1), 2015);
+        let decoded = chunk_size.decode_from(&bytes, 0, None).unwrap();
+        assert_eq!(stream::encode(&decoded), test_data);
+    }
+
+    #[test]
+    pub fn test_byte_len_count() {
+        assert_eq!(stream::byte_len(0), 0);
+        assert_eq!(stream::byte_len(1), 80);
+        assert_eq!(stream::byte_len(2016), 80 + 2015 * 44);
+        assert_eq!(stream::byte_len(2017), 2 * 80 + 2015 * 44);
+        for count in &[0, 1, 2, 2015, 2016, 2017, 5000] {
+            let len = stream::byte_len(*count);
+            assert_eq!(stream::count(len), *count);
+            assert_eq!(stream::count(len + 43), *count);
+        }
+    }
+}
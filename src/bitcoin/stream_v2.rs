@@ -0,0 +1,193 @@
+//! Second version of the compressed stream, smaller but without random access.
+//! Every chunk is:
+//!
+//! * the full 80 bytes header
+//! * the number of distinct versions of the other headers and the versions, 4 bytes each
+//! * for every other header: the index of its version, the merkle root, the time as
+//!   a zig-zag encoded difference from the time of the previous header and the nonce
+//!
+//! Numbers are LEB128 varints, a version index takes one byte up to 128 versions.
+
+use std::convert::TryFrom;
+use bitcoin::header::BlockHeader;
+use bitcoin::stream::ChunkSize;
+
+/// Stream of `block_headers` starting at `height`, which must start a chunk
+pub fn encode_from(block_headers: &[BlockHeader], height: usize, chunk_size: ChunkSize) -> Vec<u8> {
+    assert!(chunk_size.is_full(height), "v2 streams start with a chunk");
+    let mut bytes = Vec::new();
+    for chunk in block_headers.chunks(chunk_size.get()) {
+        bytes.extend_from_slice(&chunk[0].as_bytes());
+        let mut versions: Vec<[u8; 4]> = Vec::new();
+        for block_header in &chunk[1..] {
+            if !versions.contains(&block_header.version) {
+                versions.push(block_header.version);
+            }
+        }
+        write_varint(&mut bytes, versions.len() as u64);
+        for version in versions.iter() {
+            bytes.extend_from_slice(version);
+        }
+        for pair in chunk.windows(2) {
+            let (prev, block_header) = (&pair[0], &pair[1]);
+            let index = versions.iter().position(|v| *v == block_header.version).unwrap();
+            write_varint(&mut bytes, index as u64);
+            bytes.extend_from_slice(&block_header.merkle_root);
+            let delta = i64::from(block_header.timestamp()) - i64::from(prev.timestamp());
+            write_varint(&mut bytes, ((delta << 1) ^ (delta >> 63)) as u64);
+            bytes.extend_from_slice(&block_header.nonce);
+        }
+    }
+    bytes
+}
+
+pub fn encode(block_headers: &[BlockHeader], chunk_size: ChunkSize) -> Vec<u8> {
+    encode_from(block_headers, 0, chunk_size)
+}
+
+/// Rebuild the headers of a stream starting at `height`, which must start a chunk
+pub fn decode_from(
+    bytes: &[u8],
+    height: usize,
+    chunk_size: ChunkSize,
+) -> Result<Vec<BlockHeader>, &'static str> {
+    if !chunk_size.is_full(height) {
+        return Err("v2 streams start with a chunk");
+    }
+    let mut result: Vec<BlockHeader> = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let first = read_bytes(bytes, &mut pos, 80)?;
+        let mut header_bytes = [0u8; 80];
+        header_bytes.copy_from_slice(first);
+        let mut prev = BlockHeader::from_bytes(header_bytes);
+        result.push(prev);
+
+        let mut versions: Vec<[u8; 4]> = Vec::new();
+        for _ in 0..read_varint(bytes, &mut pos)? {
+            versions.push(array_4(read_bytes(bytes, &mut pos, 4)?));
+        }
+        for _ in 1..chunk_size.get() {
+            if pos == bytes.len() {
+                break;
+            }
+            let index = read_varint(bytes, &mut pos)? as usize;
+            let version = *versions.get(index).ok_or("Version index outside the dictionary")?;
+            let mut merkle_root = [0u8; 32];
+            merkle_root.copy_from_slice(read_bytes(bytes, &mut pos, 32)?);
+            let zigzag = read_varint(bytes, &mut pos)?;
+            let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            let time = i64::from(prev.timestamp())
+                .checked_add(delta)
+                .and_then(|time| u32::try_from(time).ok())
+                .ok_or("Time out of range")?;
+            let nonce = array_4(read_bytes(bytes, &mut pos, 4)?);
+            let block_header = BlockHeader {
+                version,
+                prev_blockhash: prev.hash(),
+                merkle_root,
+                time: time.to_le_bytes(),
+                bits: prev.bits,
+                nonce,
+            };
+            result.push(block_header);
+            prev = block_header;
+        }
+    }
+    Ok(result)
+}
+
+pub fn decode(bytes: &[u8], chunk_size: ChunkSize) -> Result<Vec<BlockHeader>, &'static str> {
+    decode_from(bytes, 0, chunk_size)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, &'static str> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or("Stream truncated in a varint")?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("Varint too long")
+}
+
+fn read_bytes<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], &'static str> {
+    if bytes.len() < *pos + len {
+        return Err("Stream truncated in a header");
+    }
+    *pos += len;
+    Ok(&bytes[*pos - len..*pos])
+}
+
+fn array_4(bytes: &[u8]) -> [u8; 4] {
+    [bytes[0], bytes[1], bytes[2], bytes[3]]
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::stream;
+    use bitcoin::stream::ChunkSize;
+    use bitcoin::stream_v2;
+
+    #[test]
+    pub fn test_encode_decode_v2() {
+        let mut block_headers = stream::decode(include_bytes!("../../examples/2016/0")).unwrap();
+        let chunk_20 = stream::decode(include_bytes!("../../examples/2016/20")).unwrap();
+        let chunk_21 = stream::decode(include_bytes!("../../examples/2016/21")).unwrap();
+
+        for chunk_size in &[stream::DEFAULT, ChunkSize::new(144).unwrap()] {
+            let bytes = stream_v2::encode(&block_headers, *chunk_size);
+            assert!(bytes.len() < chunk_size.byte_len(block_headers.len()));
+            let decoded = stream_v2::decode(&bytes, *chunk_size).unwrap();
+            assert_eq!(stream::encode(&decoded), stream::encode(&block_headers));
+            // a partial last chunk
+            let decoded = stream_v2::decode(&stream_v2::encode(&block_headers[..1000], *chunk_size), *chunk_size);
+            assert_eq!(decoded.unwrap()[999].hash(), block_headers[999].hash());
+        }
+
+        let mut two_chunks = chunk_20.clone();
+        two_chunks.extend_from_slice(&chunk_21);
+        let bytes = stream_v2::encode_from(&two_chunks, 40320, stream::DEFAULT);
+        let decoded = stream_v2::decode_from(&bytes, 40320, stream::DEFAULT).unwrap();
+        assert_eq!(decoded[4031].hash(), chunk_21[2015].hash());
+        assert!(stream_v2::decode_from(&bytes, 40321, stream::DEFAULT).is_err());
+        assert!(stream_v2::decode(&bytes[..bytes.len() - 1], stream::DEFAULT).is_err());
+
+        // a time delta going before 1970 or after 2106
+        let with_delta = |zigzag: u64| {
+            let mut bytes = block_headers[0].as_bytes().to_vec();
+            stream_v2::write_varint(&mut bytes, 1);
+            bytes.extend_from_slice(&block_headers[0].version);
+            stream_v2::write_varint(&mut bytes, 0);
+            bytes.extend_from_slice(&[0u8; 32]);
+            stream_v2::write_varint(&mut bytes, zigzag);
+            bytes.extend_from_slice(&[0u8; 4]);
+            stream_v2::decode(&bytes, stream::DEFAULT)
+        };
+        assert_eq!(with_delta(2).unwrap()[1].timestamp(), block_headers[0].timestamp() + 1);
+        assert!(with_delta(u64::MAX).is_err());
+        assert!(with_delta(2 * u64::from(u32::MAX)).is_err());
+
+        // time going backwards and more than one version
+        block_headers[10].time = (block_headers[9].timestamp() - 1000).to_le_bytes();
+        block_headers[11].version = [2, 0, 0, 0];
+        for height in 10..block_headers.len() {
+            block_headers[height].prev_blockhash = block_headers[height - 1].hash();
+        }
+        let bytes = stream_v2::encode(&block_headers, stream::DEFAULT);
+        let decoded = stream_v2::decode(&bytes, stream::DEFAULT).unwrap();
+        assert_eq!(stream::encode(&decoded), stream::encode(&block_headers));
+    }
+}
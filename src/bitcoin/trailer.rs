@@ -0,0 +1,120 @@
+//! Stream with a trailer after every complete chunk: the hash of the last header of the
+//! chunk. Rebuilding the chunk gives the same hash only if none of its bytes changed, so
+//! corruption is found at chunk granularity. Chunks start with a full header and don't
+//! depend on each other, a corrupted one can be downloaded again by itself.
+
+use bitcoin::header::BlockHeader;
+use bitcoin::stream::ChunkSize;
+
+/// Length in bytes of a trailer
+pub const LEN: usize = 32;
+
+/// Length of a stream with trailers of `count` headers starting from a chunk
+pub fn byte_len(chunk_size: ChunkSize, count: usize) -> usize {
+    chunk_size.byte_len(count) + count / chunk_size.get() * LEN
+}
+
+/// Offset of the chunk starting at `height` in a stream with trailers starting from genesis
+pub fn offset(chunk_size: ChunkSize, height: usize) -> usize {
+    byte_len(chunk_size, height)
+}
+
+/// Stream of `block_headers` starting at `height`, which must start a chunk
+pub fn encode_from(block_headers: &[BlockHeader], height: usize, chunk_size: ChunkSize) -> Vec<u8> {
+    assert!(chunk_size.is_full(height), "streams with trailers start with a chunk");
+    let mut bytes = Vec::with_capacity(byte_len(chunk_size, block_headers.len()));
+    for chunk in block_headers.chunks(chunk_size.get()) {
+        bytes.extend_from_slice(&chunk_size.encode(chunk));
+        if chunk.len() == chunk_size.get() {
+            bytes.extend_from_slice(&chunk[chunk.len() - 1].hash());
+        }
+    }
+    bytes
+}
+
+/// Rebuild every chunk of a stream starting at `height`, which must start a chunk.
+/// A chunk not matching its trailer is returned as the error with the height of its
+/// first header, the other chunks are still valid
+pub fn decode_chunks(
+    bytes: &[u8],
+    height: usize,
+    chunk_size: ChunkSize,
+) -> Result<Vec<Result<Vec<BlockHeader>, usize>>, &'static str> {
+    if !chunk_size.is_full(height) {
+        return Err("streams with trailers start with a chunk");
+    }
+    let chunk_len = chunk_size.byte_len(chunk_size.get());
+    let mut result = Vec::new();
+    for (i, bytes) in bytes.chunks(chunk_len + LEN).enumerate() {
+        let chunk_height = height + i * chunk_size.get();
+        if bytes.len() < chunk_len + LEN {
+            // the open chunk, without trailer, a complete chunk must have one
+            if bytes.len() >= chunk_len {
+                return Err("Incomplete trailer");
+            }
+            result.push(chunk_size.decode_from(bytes, chunk_height, None).map_err(|_| chunk_height));
+            continue;
+        }
+        let decoded = chunk_size
+            .decode_from(&bytes[..chunk_len], chunk_height, None)
+            .ok()
+            .filter(|chunk| chunk[chunk.len() - 1].hash()[..] == bytes[chunk_len..]);
+        result.push(decoded.ok_or(chunk_height));
+    }
+    Ok(result)
+}
+
+/// Rebuild the headers of a stream starting at `height`, failing at the first corrupted chunk
+pub fn decode_from(
+    bytes: &[u8],
+    height: usize,
+    chunk_size: ChunkSize,
+) -> Result<Vec<BlockHeader>, &'static str> {
+    let mut result = Vec::new();
+    for chunk in decode_chunks(bytes, height, chunk_size)? {
+        result.extend(chunk.map_err(|_| "Chunk not matching its trailer")?);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::stream;
+    use bitcoin::stream::ChunkSize;
+    use bitcoin::trailer;
+
+    #[test]
+    pub fn test_trailer() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        let chunk_size = ChunkSize::new(144).unwrap();
+
+        let bytes = trailer::encode_from(&block_headers[..1000], 0, chunk_size);
+        assert_eq!(bytes.len(), trailer::byte_len(chunk_size, 1000));
+        assert_eq!(trailer::offset(chunk_size, 288), 2 * (80 + 143 * 44 + 32));
+        let decoded = trailer::decode_from(&bytes, 0, chunk_size).unwrap();
+        assert_eq!(stream::encode(&decoded), stream::encode(&block_headers[..1000]));
+
+        // a flipped byte invalidates just its chunk
+        let mut corrupted = bytes.clone();
+        corrupted[trailer::offset(chunk_size, 288) + 100] ^= 1;
+        let chunks = trailer::decode_chunks(&corrupted, 0, chunk_size).unwrap();
+        assert_eq!(chunks.len(), 7);
+        assert_eq!(chunks[2].as_ref().err(), Some(&288));
+        assert!(chunks.iter().enumerate().all(|(i, chunk)| i == 2 || chunk.is_ok()));
+        assert!(trailer::decode_from(&corrupted, 0, chunk_size).is_err());
+
+        // the chunk downloaded again by itself
+        let start = trailer::offset(chunk_size, 288);
+        let again = trailer::decode_from(&bytes[start..start + trailer::byte_len(chunk_size, 144)], 288, chunk_size);
+        assert_eq!(again.unwrap()[143].hash(), block_headers[431].hash());
+
+        let mut corrupted = bytes.clone();
+        corrupted[trailer::offset(chunk_size, 144) - 1] ^= 1;
+        assert_eq!(trailer::decode_chunks(&corrupted, 0, chunk_size).unwrap()[0].as_ref().err(), Some(&0));
+        assert!(trailer::decode_from(&bytes[..trailer::offset(chunk_size, 144) - 1], 0, chunk_size).is_err());
+        assert!(trailer::decode_from(&bytes[..trailer::offset(chunk_size, 144) - 32], 0, chunk_size).is_err());
+        assert!(trailer::decode_from(&bytes, 1, chunk_size).is_err());
+    }
+}
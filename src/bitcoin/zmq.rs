@@ -0,0 +1,204 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Topic bitcoind uses for `zmqpubhashblock` notifications
+pub const HASHBLOCK: &[u8] = b"hashblock";
+
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+/// Receives the block hashes (big endian, as published by bitcoind) of new tips
+pub struct Subscription {
+    receiver: Receiver<[u8; 32]>,
+}
+
+impl Subscription {
+    /// Subscribe to `hashblock` on `addr` (`host:port`, the `tcp://` prefix is optional)
+    /// in a background thread, reconnecting every 10 seconds on failures
+    pub fn start(addr: &str) -> Subscription {
+        let addr = String::from(addr.trim_start_matches("tcp://"));
+        let (sender, receiver) = channel();
+        thread::spawn(move || loop {
+            if let Err(e) = subscribe(&addr, &sender) {
+                println!("{:?} with zmq {}", e, addr);
+            }
+            thread::sleep(Duration::from_secs(10));
+        });
+        Subscription { receiver }
+    }
+
+    /// Wait up to `timeout` for a new block, draining queued notifications
+    pub fn wait(&self, timeout: Duration) -> Option<[u8; 32]> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(mut hash) => {
+                while let Ok(next) = self.receiver.try_recv() {
+                    hash = next;
+                }
+                Some(hash)
+            }
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+fn subscribe(addr: &str, sender: &Sender<[u8; 32]>) -> io::Result<()> {
+    let mut stream = TcpStream::connect(addr)?;
+    handshake(&mut stream, "SUB")?;
+    let mut subscribe_body = vec![1u8];
+    subscribe_body.extend_from_slice(HASHBLOCK);
+    write_frame(&mut stream, 0, &subscribe_body)?;
+    println!("Subscribed to zmq hashblock at {}", addr);
+
+    loop {
+        let parts = read_message(&mut stream)?;
+        if parts.len() >= 2 && parts[0] == HASHBLOCK && parts[1].len() == 32 {
+            let mut hash: [u8; 32] = [0; 32];
+            hash.clone_from_slice(&parts[1]);
+            if sender.send(hash).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// ZMTP 3.0 greeting with the NULL security mechanism followed by the READY command
+pub fn handshake<S: Read + Write>(stream: &mut S, socket_type: &str) -> io::Result<()> {
+    let mut greeting: [u8; 64] = [0; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].clone_from_slice(b"NULL");
+    stream.write_all(&greeting)?;
+
+    let mut peer_greeting: [u8; 64] = [0; 64];
+    stream.read_exact(&mut peer_greeting)?;
+    if peer_greeting[0] != 0xff || peer_greeting[9] != 0x7f || peer_greeting[10] < 3 {
+        return Err(invalid_data("not a ZMTP 3 peer"));
+    }
+    if &peer_greeting[12..16] != b"NULL" {
+        return Err(invalid_data("only the NULL mechanism is supported"));
+    }
+
+    let mut ready = Vec::new();
+    ready.push(5);
+    ready.extend_from_slice(b"READY");
+    ready.push(11);
+    ready.extend_from_slice(b"Socket-Type");
+    ready.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    ready.extend_from_slice(socket_type.as_bytes());
+    write_frame(stream, FLAG_COMMAND, &ready)?;
+
+    let (flags, body) = read_frame(stream)?;
+    if flags & FLAG_COMMAND == 0 || !body.starts_with(b"\x05READY") {
+        return Err(invalid_data("expected READY command"));
+    }
+    Ok(())
+}
+
+/// Read a multipart message, skipping commands
+pub fn read_message<R: Read>(reader: &mut R) -> io::Result<Vec<Vec<u8>>> {
+    let mut parts = Vec::new();
+    loop {
+        let (flags, body) = read_frame(reader)?;
+        if flags & FLAG_COMMAND != 0 {
+            continue;
+        }
+        parts.push(body);
+        if flags & FLAG_MORE == 0 {
+            return Ok(parts);
+        }
+    }
+}
+
+/// Write a multipart message
+pub fn write_message<W: Write>(writer: &mut W, parts: &[&[u8]]) -> io::Result<()> {
+    for (i, part) in parts.iter().enumerate() {
+        let flags = if i + 1 < parts.len() { FLAG_MORE } else { 0 };
+        write_frame(writer, flags, part)?;
+    }
+    Ok(())
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags: [u8; 1] = [0; 1];
+    reader.read_exact(&mut flags)?;
+    let size = if flags[0] & FLAG_LONG != 0 {
+        let mut size: [u8; 8] = [0; 8];
+        reader.read_exact(&mut size)?;
+        u64::from_be_bytes(size) as usize
+    } else {
+        let mut size: [u8; 1] = [0; 1];
+        reader.read_exact(&mut size)?;
+        size[0] as usize
+    };
+    if size > 1024 * 1024 {
+        return Err(invalid_data("frame too big"));
+    }
+    let mut body = vec![0u8; size];
+    reader.read_exact(&mut body)?;
+    Ok((flags[0], body))
+}
+
+fn write_frame<W: Write>(writer: &mut W, flags: u8, body: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(9 + body.len());
+    if body.len() > 255 {
+        frame.push(flags | FLAG_LONG);
+        frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+    } else {
+        frame.push(flags);
+        frame.push(body.len() as u8);
+    }
+    frame.extend_from_slice(body);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::zmq;
+    use bitcoin::zmq::Subscription;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_subscription_from_stand_in_publisher() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("tcp://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            zmq::handshake(&mut stream, "PUB").unwrap();
+            let subscription = zmq::read_message(&mut stream).unwrap();
+            assert_eq!(subscription[0], b"\x01hashblock");
+            for i in 0..3u8 {
+                let hash = [i; 32];
+                let sequence = [i, 0, 0, 0];
+                zmq::write_message(&mut stream, &[b"rawtx", &[0u8; 300]]).unwrap();
+                zmq::write_message(&mut stream, &[zmq::HASHBLOCK, &hash, &sequence]).unwrap();
+                thread::sleep(Duration::from_millis(50));
+            }
+            thread::sleep(Duration::from_secs(5));
+        });
+
+        let subscription = Subscription::start(&addr);
+        let mut received = Vec::new();
+        while let Some(hash) = subscription.wait(Duration::from_secs(2)) {
+            received.push(hash[0]);
+            if hash[0] == 2 {
+                break;
+            }
+        }
+        assert_eq!(received.last(), Some(&2));
+        assert!(subscription.wait(Duration::from_millis(100)).is_none());
+    }
+}
@@ -0,0 +1,275 @@
+use futures::{Future, Stream};
+use hyper::{Client, Method, Request, StatusCode, Uri};
+use hyper::header::{ByteRangeSpec, Range};
+use tokio_core::reactor::Core;
+use std::io;
+use attestation::{Attestation, SignedAttestation};
+use bitcoin::checkpoint::Checkpoints;
+use bitcoin::header;
+use bitcoin::header::BlockHeader;
+use bitcoin::rpc::to_io_error;
+use bitcoin::stream;
+use bitcoin::stream::ChunkSize;
+use client::parallel;
+use server;
+
+/// Client of the `/bitcoin-headers` endpoint, downloading and verifying the stream
+pub struct HeadersClient {
+    url: String,
+    chunk_size: ChunkSize,
+    checkpoints: Checkpoints,
+    trust_checkpoints: bool,
+    operator_key: Option<[u8; 32]>,
+    threads: usize,
+}
+
+impl HeadersClient {
+    /// `url` of the endpoint, like `http://localhost:3000/bitcoin-headers`, enforcing
+    /// the mainnet checkpoints
+    pub fn new(url: &str) -> HeadersClient {
+        HeadersClient {
+            url: String::from(url),
+            chunk_size: stream::DEFAULT,
+            checkpoints: Checkpoints::defaults("main"),
+            trust_checkpoints: false,
+            operator_key: None,
+            threads: parallel::available_threads(),
+        }
+    }
+
+    /// The chunk size of the served stream
+    pub fn chunk_size(mut self, chunk_size: ChunkSize) -> HeadersClient {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn checkpoints(mut self, checkpoints: Checkpoints) -> HeadersClient {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    /// Check only the linkage of the headers up to the last checkpoint, the chain must
+    /// then reach it since the checkpoint hash is what commits to them
+    pub fn trust_checkpoints(mut self, trust: bool) -> HeadersClient {
+        self.trust_checkpoints = trust;
+        self
+    }
+
+    /// Threads decoding and verifying the headers, all the available ones by default
+    pub fn threads(mut self, threads: usize) -> HeadersClient {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// The ed25519 public key of the operator of the server, signing the served tips
+    pub fn operator_key(mut self, public_key: [u8; 32]) -> HeadersClient {
+        self.operator_key = Some(public_key);
+        self
+    }
+
+    /// Download the tip signed by the operator, `Attestation::check_chain` then tells if
+    /// a fetched chain is the one of the operator
+    pub fn fetch_attestation(&self) -> io::Result<Attestation> {
+        let public_key = self.operator_key.ok_or_else(|| invalid_data("no operator key"))?;
+        let bytes = self.get(&format!("{}/attestation", self.url), None)?;
+        let signed = SignedAttestation::parse(&bytes).map_err(invalid_data)?;
+        let attestation = *signed.verify(&public_key).map_err(invalid_data)?;
+        match attestation.chunk_size as usize == self.chunk_size.get() {
+            true => Ok(attestation),
+            false => Err(invalid_data("attestation of another chunk size")),
+        }
+    }
+
+    /// Download the headers following `known`, an already verified chain starting from
+    /// genesis, and return the whole verified chain.
+    /// An error is returned if the served chain doesn't extend `known`, for example after
+    /// a reorg, retrying with fewer known headers is then needed
+    pub fn fetch(&self, known: &[BlockHeader]) -> io::Result<Vec<BlockHeader>> {
+        let height = known.len();
+        let bytes = self.get(&self.url, Some(self.chunk_size.offset(height)))?;
+        let block_headers = parallel::decode_from(self.chunk_size, &bytes, height, known.last(), self.threads)
+            .map_err(invalid_data)?;
+        let last_checkpoint = match self.trust_checkpoints {
+            true => self.checkpoints.last_height(),
+            false => None,
+        };
+        let mut chain = known.to_vec();
+        chain.extend(block_headers);
+        parallel::verify(&chain, height, self.threads, |prev_headers, block_header, hash, prev_hash| {
+            let height = prev_headers.len();
+            let verified = match last_checkpoint {
+                Some(last) if height <= last => verify_linkage(block_header, hash, prev_hash),
+                _ => verify_next(prev_headers, block_header, hash, prev_hash),
+            };
+            verified.and_then(|_| self.checkpoints.check(height, hash))
+        }).map_err(|(height, e)| invalid_data(&format!("header at height {} {}", height, e)))?;
+        match last_checkpoint {
+            Some(last) if chain.len() > height && chain.len() <= last => {
+                Err(invalid_data("chain not reaching the last checkpoint"))
+            }
+            _ => Ok(chain),
+        }
+    }
+
+    /// The body of `url`, from byte `offset` to the end if given
+    fn get(&self, url: &str, offset: Option<usize>) -> io::Result<Vec<u8>> {
+        let mut core = Core::new()?;
+        let client = Client::new(&core.handle());
+        let uri: Uri = url.parse().map_err(|_| invalid_data("invalid url"))?;
+        let mut request = Request::new(Method::Get, uri);
+        if let Some(offset) = offset {
+            request
+                .headers_mut()
+                .set(Range::Bytes(vec![ByteRangeSpec::AllFrom(offset as u64)]));
+        }
+
+        let work = client.request(request).and_then(|res| {
+            let status = res.status();
+            let chunk_size = res
+                .headers()
+                .get_raw(server::CHUNK_SIZE_HEADER)
+                .and_then(|raw| raw.one())
+                .map(|value| value.to_vec());
+            res.body().concat2().map(move |body| (status, chunk_size, body))
+        });
+
+        let (status, chunk_size, body) = core.run(work).map_err(to_io_error)?;
+        if chunk_size.is_some_and(|value| value != self.chunk_size.get().to_string().as_bytes()) {
+            return Err(invalid_data("stream served with another chunk size"));
+        }
+        match status {
+            StatusCode::Ok | StatusCode::PartialContent => Ok(body.to_vec()),
+            StatusCode::RangeNotSatisfiable => Err(invalid_data("known chain longer than the served one")),
+            status => Err(io::Error::other(format!("request failed with status {}", status))),
+        }
+    }
+}
+
+/// Check `block_header`, with hash `hash`, follows the header with hash `prev_hash`, or is
+/// the genesis without a previous header
+pub fn verify_linkage(
+    block_header: &BlockHeader,
+    hash: &[u8; 32],
+    prev_hash: Option<&[u8; 32]>,
+) -> Result<(), &'static str> {
+    match prev_hash {
+        Some(prev_hash) if block_header.prev_blockhash == *prev_hash => Ok(()),
+        Some(_) => Err("doesn't connect to the previous header"),
+        None if *hash == BlockHeader::genesis().hash() => Ok(()),
+        None => Err("is not the genesis"),
+    }
+}
+
+/// Check `block_header`, with hash `hash`, extends `chain`, starting with the genesis and
+/// with the last header hashing to `prev_hash`, with valid linkage, difficulty, proof of
+/// work and time
+pub fn verify_next(
+    chain: &[BlockHeader],
+    block_header: &BlockHeader,
+    hash: &[u8; 32],
+    prev_hash: Option<&[u8; 32]>,
+) -> Result<(), &'static str> {
+    let height = chain.len();
+    let (prev, prev_hash) = match (chain.last(), prev_hash) {
+        (Some(prev), Some(prev_hash)) => (prev, prev_hash),
+        _ => return verify_linkage(block_header, hash, None),
+    };
+    let period_first = height.checked_sub(header::DIFFICULTY_INTERVAL).map(|h| &chain[h]);
+    block_header.check_next(hash, height, prev, prev_hash, period_first)?;
+    block_header.check_time(&chain[height.saturating_sub(header::MEDIAN_TIME_SPAN)..])
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use attestation::{Attestation, Signer};
+    use bitcoin::checkpoint::Checkpoints;
+    use bitcoin::envelope;
+    use bitcoin::stream;
+    use client::http::HeadersClient;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Stand-in server answering a range request from a fixed stream
+    fn serve(mut stream: TcpStream, bytes: &[u8]) {
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).unwrap();
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let request = String::from_utf8(request).unwrap().to_lowercase();
+        let start: usize = request
+            .lines()
+            .filter_map(|line| line.strip_prefix("range: bytes="))
+            .map(|range| range.trim_end_matches('-').parse().unwrap())
+            .next()
+            .unwrap_or(0);
+        let body = &bytes[start..];
+        let response = format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).unwrap();
+        stream.write_all(body).unwrap();
+    }
+
+    #[test]
+    pub fn test_fetch() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        let mut tampered = test_data.clone();
+        tampered[stream::offset(1500) + 10] ^= 1;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/bitcoin-headers", listener.local_addr().unwrap());
+        let main = envelope::network_magic("main").unwrap();
+        let signer = Signer::new(&[7; 32]);
+        let attestation = Attestation::new(main, stream::DEFAULT, &block_headers).unwrap();
+        let signed = signer.sign(&attestation).as_bytes().to_vec();
+        let served = vec![
+            test_data.clone(),
+            test_data.clone(),
+            tampered,
+            test_data.clone(),
+            signed.clone(),
+            signed,
+        ];
+        thread::spawn(move || {
+            for bytes in served {
+                let (stream, _) = listener.accept().unwrap();
+                serve(stream, &bytes);
+            }
+        });
+        let client = HeadersClient::new(&url).threads(3);
+
+        let chain = client.fetch(&[]).unwrap();
+        assert_eq!(chain.len(), 2016);
+        assert_eq!(stream::encode(&chain), test_data);
+
+        // resuming from a known height downloads just the rest
+        let chain = client.fetch(&block_headers[..1000]).unwrap();
+        assert_eq!(chain[2015].hash(), block_headers[2015].hash());
+
+        let error = client.fetch(&block_headers[..1000]).unwrap_err();
+        assert!(format!("{}", error).contains("height 1500"));
+
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.add(2000, [0; 32]);
+        let client = HeadersClient::new(&url).checkpoints(checkpoints).trust_checkpoints(true);
+        let error = client.fetch(&block_headers[..1000]).unwrap_err();
+        assert!(format!("{}", error).contains("height 2000 doesn't match the checkpoint"));
+
+        assert!(client.fetch_attestation().is_err());
+        let client = HeadersClient::new(&url).operator_key(signer.public_key());
+        let fetched = client.fetch_attestation().unwrap();
+        assert!(fetched.check_chain(&block_headers).is_ok());
+        let client = HeadersClient::new(&url).operator_key(Signer::new(&[8; 32]).public_key());
+        assert!(client.fetch_attestation().is_err());
+    }
+}
@@ -1,130 +1,438 @@
-use std::sync::{Arc, Mutex};
+pub mod http;
+pub mod parallel;
+
+use std::io;
+use std::sync::{Arc, RwLock};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use util::hex::ToHex;
+use accumulator::Accumulator;
+use flyclient::Mmr;
+use nipopow;
+use bitcoin::hash;
+use bitcoin::header;
 use bitcoin::header::BlockHeader;
-use bitcoin;
-use std::collections::HashMap;
-use bitcoin::Config;
+use bitcoin::{Config, Source};
+use bitcoin::checkpoint::Checkpoints;
+use bitcoin::zmq::Subscription;
+use bitcoin::source::HeaderSource;
+use bitcoin::rpc::RpcSource;
+use bitcoin::rest::RestSource;
+use bitcoin::p2p::P2pSource;
+use bitcoin::blk::FilesSource;
+use store::HeaderStore;
+
+/// Headers asked to the source in a single call
+const BATCH: usize = 2000;
+
+/// Headers closer than this to the tip are not served, since they could be reorged
+const CONFIRMATIONS: usize = 6;
 
 pub fn start(
-    block_headers_bytes: Arc<Mutex<Vec<u8>>>,
+    store: Arc<dyn HeaderStore>,
     config: &Config,
+    accumulator: Arc<RwLock<Accumulator>>,
 ) {
-    let start = Instant::now();
+    let subscription = config.zmq().map(Subscription::start);
+    let mut source: Box<dyn HeaderSource> = match config.source() {
+        Source::Rpc => Box::new(RpcSource::new(config.clone())),
+        Source::Rest => Box::new(RestSource::new(config.clone())),
+        Source::P2p => Box::new(P2pSource::new(config.peer())),
+        Source::Files => match config.blocks() {
+            Some(blocks) => Box::new(FilesSource::new(blocks)),
+            None => {
+                println!("Cannot find the blocks directory");
+                return;
+            }
+        },
+    };
+    let sync = SyncState::new(store)
+        .checkpoints(config.checkpoints())
+        .trust_checkpoints(config.trust_checkpoints())
+        .accumulator(accumulator);
+    run(sync, &mut source, &subscription);
+}
 
-    let genesis_block_hash =
-        String::from("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
-    let mut block_hash: String = genesis_block_hash.clone();
+/// Keep the store of `sync` in sync with `source`, forever
+pub fn run<S: HeaderSource + ?Sized>(
+    mut sync: SyncState,
+    source: &mut S,
+    subscription: &Option<Subscription>,
+) {
+    let start = Instant::now();
     let mut last_block: usize = 0;
-    let mut min_block_hash: String = genesis_block_hash;
-
-    let mut synced_height: usize = 0;
-    let mut block_headers_map = HashMap::new();
 
     loop {
-        let r = bitcoin::rpc::get_block_header(
-            block_hash.clone(),
-            &config,
-        );
-        match r {
-            Ok(block_header_rpc_response) => {
-                let block_header_rpc: bitcoin::rpc::BlockHeaderRpc =
-                    block_header_rpc_response.result;
-                let height = block_header_rpc.height.clone() as usize;
-                if last_block == 0 && height % 1000 == 0 {
+        match sync.step(source) {
+            Ok(tip) => {
+                if sync.synced_height() + CONFIRMATIONS < tip {
                     println!(
-                        "Block #{} with hash {} elapsed {} seconds",
-                        height,
-                        block_hash,
+                        "Block #{} elapsed {} seconds",
+                        sync.synced_height(),
                         start.elapsed().as_secs()
                     );
-                    synced_height = sync(
-                        &mut block_headers_map,
-                        &block_headers_bytes,
-                        height,
-                        synced_height,
-                    );
-                }
-
-                let block_hash_option = block_header_rpc.nextblockhash.clone();
-                let sleep = match block_hash_option {
-                    Some(val) => {
-                        block_hash = val;
-                        let block_header = BlockHeader::from_block_header_rpc(block_header_rpc);
-
-                        block_headers_map.insert(height, block_header);
-                        let hash_hex = block_header.hash_be().to_hex();
-                        if min_block_hash > hash_hex {
-                            min_block_hash = hash_hex;
-                            println!("Block #{} with hash {} is the min!", height, min_block_hash);
-                        }
-
-                        false
+                } else {
+                    if tip != last_block {
+                        println!("Block #{} synced_height {}", tip, sync.synced_height());
                     }
-                    None => {
-                        if height != last_block {
-                            synced_height = sync(
-                                &mut block_headers_map,
-                                &block_headers_bytes,
-                                height,
-                                synced_height,
-                            );
-                            println!(
-                                "Block #{} with hash {} synced_height {}",
-                                height,
-                                block_hash,
-                                synced_height
-                            );
-                        }
-                        last_block = height;
-                        block_hash = block_headers_map
-                            .get(&(height - 6))
-                            .unwrap()
-                            .hash_be()
-                            .to_hex(); //going back 6 blocks to support reorgs
-
-                        true
-                    }
-                };
-
-                if sleep {
-                    thread::sleep(Duration::from_secs(60));
+                    last_block = tip;
+                    wait_new_block(subscription);
                 }
             }
             Err(e) => {
-                println!("{:?} with hash {}", e, block_hash);
+                println!("{:?} at synced_height {}", e, sync.synced_height());
                 thread::sleep(Duration::from_secs(10));
             }
         }
     }
 }
 
-fn sync(
-    block_headers_map: &mut HashMap<usize, BlockHeader>,
-    block_headers_bytes: &Arc<Mutex<Vec<u8>>>,
-    height: usize,
-    synced_height: usize,
-) -> usize {
-    let sync_to_option = height.checked_sub(6);
-    match sync_to_option {
-        Some(sync_to) => {
-            let mut block_headers_bytes_lock = block_headers_bytes.lock().unwrap();
-            for i in synced_height..sync_to {
-                match i % 2016 {
-                    0 => block_headers_bytes_lock
-                        .extend(block_headers_map.remove(&i).unwrap().as_bytes().into_iter()),
-                    _ => block_headers_bytes_lock.extend(
-                        block_headers_map
-                            .remove(&i)
-                            .unwrap()
-                            .as_compressed_bytes()
-                            .into_iter(),
-                    ),
+/// Wait for the next block notification, polling every 60 seconds without zmq
+fn wait_new_block(subscription: &Option<Subscription>) {
+    match *subscription {
+        Some(ref subscription) => {
+            subscription.wait(Duration::from_secs(60));
+        }
+        None => thread::sleep(Duration::from_secs(60)),
+    }
+}
+
+/// Keeps a `HeaderStore` following the best chain of a `HeaderSource`
+pub struct SyncState {
+    store: Arc<dyn HeaderStore>,
+    mmr: Option<Arc<RwLock<Mmr>>>,
+    accumulator: Option<Arc<RwLock<Accumulator>>>,
+    max_level: usize,
+    checkpoints: Checkpoints,
+    trust_checkpoints: bool,
+}
+
+impl SyncState {
+    pub fn new(store: Arc<dyn HeaderStore>) -> SyncState {
+        SyncState {
+            store,
+            mmr: None,
+            accumulator: None,
+            max_level: 0,
+            checkpoints: Checkpoints::new(),
+            trust_checkpoints: false,
+        }
+    }
+
+    /// Reject headers not matching `checkpoints`
+    pub fn checkpoints(mut self, checkpoints: Checkpoints) -> SyncState {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    /// Skip the time checks of the headers up to the last checkpoint
+    pub fn trust_checkpoints(mut self, trust: bool) -> SyncState {
+        self.trust_checkpoints = trust;
+        self
+    }
+
+    /// Keep a merkle mountain range of the synced headers for FlyClient proofs, enabling it
+    /// hashes the whole store and keeps a node for every header in memory
+    pub fn flyclient(mut self, enabled: bool) -> SyncState {
+        self.mmr = match enabled {
+            true => Some(Arc::new(RwLock::new(Mmr::from_store(&*self.store)))),
+            false => None,
+        };
+        self
+    }
+
+    /// Merkle mountain range of the synced headers, kept up to date by the sync if enabled
+    /// with `flyclient`
+    pub fn mmr(&self) -> Option<Arc<RwLock<Mmr>>> {
+        self.mmr.clone()
+    }
+
+    /// Keep `accumulator` following the completed periods of the synced headers, so the
+    /// server reads it instead of hashing the store on its event loop
+    pub fn accumulator(mut self, accumulator: Arc<RwLock<Accumulator>>) -> SyncState {
+        self.accumulator = Some(accumulator);
+        self
+    }
+
+    /// Number of headers in the store, which is also the next height to sync
+    pub fn synced_height(&self) -> usize {
+        self.store.len()
+    }
+
+    /// Undo a reorg and append up to `BATCH` headers, returns the source tip height
+    pub fn step<S: HeaderSource + ?Sized>(&mut self, source: &mut S) -> io::Result<usize> {
+        let result = self.sync_batch(source);
+        self.update_accumulator();
+        result
+    }
+
+    /// Update the accumulator, if any, on a copy so readers don't wait for the hashing
+    fn update_accumulator(&self) {
+        if let Some(ref accumulator) = self.accumulator {
+            let mut updated = accumulator.read().unwrap().clone();
+            updated.update(&*self.store);
+            *accumulator.write().unwrap() = updated;
+        }
+    }
+
+    fn sync_batch<S: HeaderSource + ?Sized>(&mut self, source: &mut S) -> io::Result<usize> {
+        let tip = source.tip()?;
+        let sync_to = tip.saturating_sub(CONFIRMATIONS);
+
+        let fork_height = self.fork_height(source, tip)?;
+        if fork_height < self.synced_height() {
+            println!(
+                "Reorg, removing headers from #{} to #{}",
+                fork_height,
+                self.synced_height() - 1
+            );
+            self.store.truncate(fork_height)?;
+            if let Some(ref mmr) = self.mmr {
+                mmr.write().unwrap().truncate(fork_height);
+            }
+        }
+
+        if self.synced_height() >= sync_to {
+            return Ok(tip);
+        }
+        let count = BATCH.min(sync_to - self.synced_height());
+        let block_headers = source.headers_from(self.synced_height(), count)?;
+        let (valid, result) = match self.check_headers(&block_headers) {
+            Ok(()) => (block_headers.len(), Ok(tip)),
+            Err((i, e)) => (i, Err(e)),
+        };
+        self.append(&block_headers[..valid])?;
+        result
+    }
+
+    /// Check `block_headers` follow the stored ones like a full node does: linkage, bits,
+    /// proof of work, times and checkpoints. Up to a trusted checkpoint only the linkage and
+    /// the checkpoints are checked. On failure returns the index of the first invalid header
+    fn check_headers(&self, block_headers: &[BlockHeader]) -> Result<(), (usize, io::Error)> {
+        let height = self.synced_height();
+        let start = height.saturating_sub(header::MEDIAN_TIME_SPAN);
+        let mut prev_headers: Vec<BlockHeader> =
+            (start..height).filter_map(|h| self.store.header_at(h)).collect();
+        let mut prev_hash = height.checked_sub(1).and_then(|h| self.store.hash_at(h));
+        let hashes = hash::headers(block_headers);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+        let trusted_height = match self.trust_checkpoints {
+            true => self.checkpoints.last_height(),
+            false => None,
+        };
+        for (i, (block_header, hash)) in block_headers.iter().zip(&hashes).enumerate() {
+            let checked = match (trusted_height, prev_headers.last(), prev_hash) {
+                (Some(trusted_height), _, _) if height + i <= trusted_height => {
+                    http::verify_linkage(block_header, hash, prev_hash.as_ref())
+                }
+                (_, Some(prev), Some(ref prev_hash)) => {
+                    let period_first = self.period_first(height + i, block_headers);
+                    block_header
+                        .check_next(hash, height + i, prev, prev_hash, period_first.as_ref())
+                        .and_then(|_| block_header.check_time(&prev_headers))
+                        .and_then(|_| block_header.check_future_time(now))
                 }
+                _ => http::verify_linkage(block_header, hash, None),
+            };
+            let checked = checked.and_then(|_| self.checkpoints.check(height + i, hash));
+            if let Err(e) = checked {
+                let message = format!("header at height {} {}", height + i, e);
+                return Err((i, io::Error::new(io::ErrorKind::InvalidData, message)));
+            }
+            prev_headers.push(*block_header);
+            prev_hash = Some(*hash);
+        }
+        Ok(())
+    }
+
+    /// The first header of the period before `height`, from the store or from
+    /// `block_headers` following it
+    fn period_first(&self, height: usize, block_headers: &[BlockHeader]) -> Option<BlockHeader> {
+        let first = height.checked_sub(header::DIFFICULTY_INTERVAL)?;
+        match first.checked_sub(self.synced_height()) {
+            Some(i) => block_headers.get(i).cloned(),
+            None => self.store.header_at(first),
+        }
+    }
+
+    /// Height of the first header in the store not in the source best chain
+    fn fork_height<S: HeaderSource + ?Sized>(&self, source: &mut S, tip: usize) -> io::Result<usize> {
+        let mut height = self.synced_height().min(tip + 1);
+        while height > 0 {
+            if Some(source.header_at(height - 1)?.hash()) == self.store.hash_at(height - 1) {
+                break;
             }
-            sync_to
+            height -= 1;
         }
-        None => synced_height,
+        Ok(height)
+    }
+
+    fn append(&mut self, block_headers: &[BlockHeader]) -> io::Result<()> {
+        let height = self.synced_height();
+        let extended = self.store.extend(block_headers);
+        let appended = self.synced_height() - height;
+        if let Some(ref mmr) = self.mmr {
+            let mut mmr = mmr.write().unwrap();
+            for block_header in &block_headers[..appended] {
+                mmr.push(block_header);
+            }
+        }
+        extended?;
+
+        for (i, block_header) in block_headers.iter().enumerate() {
+            let level = nipopow::level(block_header);
+            if level > self.max_level {
+                self.max_level = level;
+                println!(
+                    "Block #{} with hash {} has level {}, the highest!",
+                    height + i,
+                    block_header.hash_be().to_hex(),
+                    level
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use accumulator::Accumulator;
+    use bitcoin::checkpoint::Checkpoints;
+    use bitcoin::header;
+    use bitcoin::header::BlockHeader;
+    use bitcoin::merkle;
+    use bitcoin::source::MemorySource;
+    use bitcoin::stream;
+    use client::SyncState;
+    use flyclient::Mmr;
+    use std::sync::{Arc, RwLock};
+    use store::{HeaderStore, MemoryStore};
+
+    #[test]
+    pub fn test_sync_and_reorg() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        let mut source = MemorySource::new(block_headers[..1990].to_vec());
+        let store = Arc::new(MemoryStore::new());
+        assert!(SyncState::new(store.clone()).mmr().is_none());
+        // the fork below has no proof of work, it's accepted as trusted up to a checkpoint
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.add(2100, [0; 32]);
+        let mut sync = SyncState::new(store.clone())
+            .flyclient(true)
+            .checkpoints(checkpoints)
+            .trust_checkpoints(true);
+
+        assert_eq!(sync.step(&mut source).unwrap(), 1989);
+        assert_eq!(sync.synced_height(), 1983);
+        assert_eq!(
+            store.read_range(0, store.byte_len()).unwrap(),
+            stream::encode(&block_headers[..1983])
+        );
+
+        // a fork from height 1980 with more blocks than the original chain
+        let mut fork: Vec<BlockHeader> = Vec::new();
+        let mut prev_hash = block_headers[1979].hash();
+        for block_header in &block_headers[1980..2016] {
+            let mut fork_header = *block_header;
+            fork_header.prev_blockhash = prev_hash;
+            fork_header.nonce = [0; 4];
+            prev_hash = fork_header.hash();
+            fork.push(fork_header);
+        }
+        source.replace_from(1980, fork.clone());
+
+        assert_eq!(sync.step(&mut source).unwrap(), 2015);
+        assert_eq!(sync.synced_height(), 2009);
+        let mut expected = block_headers[..1980].to_vec();
+        expected.extend_from_slice(&fork[..29]);
+        assert_eq!(
+            store.read_range(0, store.byte_len()).unwrap(),
+            stream::encode(&expected)
+        );
+
+        assert_eq!(sync.step(&mut source).unwrap(), 2015);
+        assert_eq!(sync.synced_height(), 2009);
+        let mmr = sync.mmr().unwrap();
+        assert_eq!(mmr.read().unwrap().len(), 2009);
+        assert_eq!(mmr.read().unwrap().root(), Mmr::from_store(&*store).root());
+
+        // without trusting them the forked headers are rejected
+        let mut untrusted = SyncState::new(Arc::new(MemoryStore::new()));
+        assert!(untrusted.step(&mut source).is_err());
+        assert_eq!(untrusted.synced_height(), 1980);
+    }
+
+    #[test]
+    pub fn test_sync_rejects_invalid_times() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let mut block_headers = stream::decode(&test_data).unwrap();
+        let median = header::median_time(&block_headers[89..100]);
+        block_headers[100].time = median.to_le_bytes();
+        for height in 101..block_headers.len() {
+            block_headers[height].prev_blockhash = block_headers[height - 1].hash();
+        }
+        let mut source = MemorySource::new(block_headers);
+        let store = Arc::new(MemoryStore::new());
+        let mut sync = SyncState::new(store.clone());
+
+        assert!(sync.step(&mut source).is_err());
+        assert_eq!(sync.synced_height(), 100);
+    }
+
+    #[test]
+    pub fn test_sync_checkpoints() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        let mut source = MemorySource::new(block_headers.clone());
+
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.add(500, [0; 32]);
+        let mut sync = SyncState::new(Arc::new(MemoryStore::new())).checkpoints(checkpoints);
+        assert!(sync.step(&mut source).is_err());
+        assert_eq!(sync.synced_height(), 500);
+
+        // times aren't checked up to a trusted checkpoint
+        let mut tampered = block_headers.clone();
+        tampered[100].time = [0; 4];
+        for height in 101..tampered.len() {
+            tampered[height].prev_blockhash = tampered[height - 1].hash();
+        }
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.add(500, tampered[500].hash());
+        let mut source = MemorySource::new(tampered);
+        let mut sync = SyncState::new(Arc::new(MemoryStore::new()))
+            .checkpoints(checkpoints)
+            .trust_checkpoints(true);
+        assert!(sync.step(&mut source).is_err());
+        // the headers after it, rehashed, fail their proof of work
+        assert_eq!(sync.synced_height(), 501);
+    }
+
+    #[test]
+    pub fn test_sync_accumulator() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+        let mut source = MemorySource::new(block_headers.clone());
+        let store = Arc::new(MemoryStore::new());
+        store.extend(&block_headers).unwrap();
+        let accumulator = Arc::new(RwLock::new(Accumulator::new()));
+        let mut sync = SyncState::new(store.clone()).accumulator(accumulator.clone());
+
+        // built by the first step, even without headers to sync
+        sync.step(&mut source).unwrap();
+        let hashes: Vec<[u8; 32]> = block_headers.iter().map(BlockHeader::hash).collect();
+        assert_eq!(accumulator.read().unwrap().period_roots(), vec![merkle::root(&hashes)]);
+
+        // the period is dropped when it's no longer complete
+        store.truncate(2000).unwrap();
+        sync.step(&mut source).unwrap();
+        assert_eq!(sync.synced_height(), 2009);
+        assert!(accumulator.read().unwrap().period_roots().is_empty());
     }
 }
@@ -0,0 +1,137 @@
+//! Decoding and verification of a stream on many threads. Every chunk starts with a full
+//! header, so the chunks are decoded independently, and every header is then verified
+//! against the decoded chain, the headers at the start of a range of a thread linking to
+//! the ones of the previous range.
+
+use std::thread;
+use bitcoin::hash;
+use bitcoin::header::BlockHeader;
+use bitcoin::stream::ChunkSize;
+
+/// Threads available to the process
+pub fn available_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Split `jobs` in `threads` runs of consecutive jobs and run them with `f`, returning
+/// the results in the order of `jobs`
+fn run<J, R, F>(jobs: &[J], threads: usize, f: F) -> Vec<R>
+where
+    J: Sync,
+    R: Send,
+    F: Fn(&J) -> R + Sync,
+{
+    if threads <= 1 || jobs.len() <= 1 {
+        return jobs.iter().map(&f).collect();
+    }
+    let per_thread = jobs.len().div_ceil(threads);
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .chunks(per_thread)
+            .map(|run| scope.spawn(move || run.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("decoding thread panicked"))
+            .collect()
+    })
+}
+
+/// Rebuild the headers of the stream from the header at `height` on `threads` threads, like
+/// `ChunkSize::decode_from`
+pub fn decode_from(
+    chunk_size: ChunkSize,
+    bytes: &[u8],
+    height: usize,
+    prev: Option<&BlockHeader>,
+    threads: usize,
+) -> Result<Vec<BlockHeader>, &'static str> {
+    // the headers before the first chunk start need `prev`
+    let first_chunk = height.next_multiple_of(chunk_size.get());
+    let head_len = chunk_size.range_len(height, first_chunk).min(bytes.len());
+    let chunk_len = chunk_size.byte_len(chunk_size.get());
+    let mut jobs = vec![(height, &bytes[..head_len])];
+    for (i, chunk) in bytes[head_len..].chunks(chunk_len).enumerate() {
+        jobs.push((first_chunk + i * chunk_size.get(), chunk));
+    }
+    let decoded = run(&jobs, threads, |&(start, chunk)| match start == height {
+        true => chunk_size.decode_from(chunk, start, prev),
+        false => chunk_size.decode_from(chunk, start, None),
+    });
+    let mut result = Vec::with_capacity(chunk_size.count(bytes.len()));
+    for block_headers in decoded {
+        result.extend(block_headers?);
+    }
+    Ok(result)
+}
+
+/// Hashes of `block_headers` on `threads` threads
+fn hashes(block_headers: &[BlockHeader], threads: usize) -> Vec<[u8; 32]> {
+    let mut hashes = vec![[0u8; 32]; block_headers.len()];
+    let per_thread = block_headers.len().div_ceil(threads.max(1)).max(1);
+    thread::scope(|scope| {
+        for (block_headers, hashes) in block_headers.chunks(per_thread).zip(hashes.chunks_mut(per_thread)) {
+            scope.spawn(move || hash::headers_into(block_headers, hashes));
+        }
+    });
+    hashes
+}
+
+/// Check the headers of `chain` from height `start` with `verify`, given the headers before,
+/// the header, its hash and the hash of the previous header, on `threads` threads. Every
+/// header is hashed once. On failure returns the lowest invalid height
+pub fn verify<F>(chain: &[BlockHeader], start: usize, threads: usize, verify: F) -> Result<(), (usize, &'static str)>
+where
+    F: Fn(&[BlockHeader], &BlockHeader, &[u8; 32], Option<&[u8; 32]>) -> Result<(), &'static str> + Sync,
+{
+    // from the header before `start`, the first one linked to
+    let first = start.saturating_sub(1).min(chain.len());
+    let hashes = hashes(&chain[first..], threads);
+    let hash_at = |height: usize| &hashes[height - first];
+    let heights: Vec<usize> = (start..chain.len()).collect();
+    let per_thread = heights.len().div_ceil(threads.max(1)).max(1);
+    let ranges: Vec<&[usize]> = heights.chunks(per_thread).collect();
+    let checked = run(&ranges, threads, |range| {
+        for &height in range.iter() {
+            let prev_hash = height.checked_sub(1).map(hash_at);
+            verify(&chain[..height], &chain[height], hash_at(height), prev_hash).map_err(|e| (height, e))?;
+        }
+        Ok(())
+    });
+    checked.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::stream;
+    use client::http;
+    use client::parallel;
+
+    #[test]
+    pub fn test_parallel() {
+        let block_headers = stream::decode(include_bytes!("../../examples/2016/0")).unwrap();
+        let chunk_size = stream::ChunkSize::new(144).unwrap();
+        let mut test_data = chunk_size.encode(&block_headers);
+        for threads in 1..5 {
+            let decoded = parallel::decode_from(chunk_size, &test_data, 0, None, threads).unwrap();
+            assert_eq!(chunk_size.encode(&decoded), test_data);
+            let start = chunk_size.offset(100);
+            let rest = parallel::decode_from(chunk_size, &test_data[start..], 100, Some(&decoded[99]), threads);
+            assert_eq!(rest.unwrap().last().unwrap().hash(), block_headers[2015].hash());
+            let truncated = &test_data[..test_data.len() - 1];
+            assert!(parallel::decode_from(chunk_size, truncated, 0, None, threads).is_err());
+            assert_eq!(parallel::verify(&block_headers, 0, threads, http::verify_next), Ok(()));
+        }
+
+        // the headers after a tampered one are rebuilt on a changed hash and fail too
+        test_data[chunk_size.offset(1500) + 10] ^= 1;
+        let block_headers = parallel::decode_from(chunk_size, &test_data, 0, None, 3).unwrap();
+        for threads in 1..5 {
+            let verified = parallel::verify(&block_headers, 0, threads, http::verify_next);
+            assert_eq!(verified.unwrap_err().0, 1500);
+            assert!(parallel::verify(&block_headers[..1500], 0, threads, http::verify_next).is_ok());
+        }
+    }
+}
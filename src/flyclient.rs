@@ -0,0 +1,361 @@
+//! Merkle mountain range over the header hashes and their work, and FlyClient proofs,
+//! see https://eprint.iacr.org/2019/226
+//!
+//! Every node commits to the hashes and to the total work of the headers below it, so a
+//! path from a header to the root also tells the work of the chain before the header.
+//! A proof samples headers by work, denser near the tip: as many samples fall in the
+//! first half of the work as in the quarter after it, in the eighth after that and so on
+//! down to the last 1/1024 of the work. The samples are chosen
+//! from the root, so a prover can't pick them, and a chain with less work than the one
+//! claimed by the root fails a sample with high probability.
+//!
+//! Bitcoin headers don't commit to the range of the previous headers, the root is the
+//! claim of the prover: a proof shows the work behind the root, the root must be
+//! compared with the one of other provers.
+
+use bitcoin::header::BlockHeader;
+use bitcoin::hash::sha256d;
+use store::HeaderStore;
+use util::uint::Uint256;
+
+/// Samples of a proof
+pub const DEFAULT_SAMPLES: usize = 64;
+
+/// Work ranges sampled, the last one is the last 1/1024 of the work
+const BINADES: u32 = 10;
+
+const OVERFLOW: &str = "Work overflowing";
+
+/// Node of the range, a header or a subtree of headers
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Node {
+    pub hash: [u8; 32],
+    /// Total work of the headers below
+    pub work: Uint256,
+}
+
+impl Node {
+    pub fn leaf(block_header: &BlockHeader) -> Node {
+        Node {
+            hash: block_header.hash(),
+            work: block_header.work(),
+        }
+    }
+
+    /// Parent of two nodes of a chain of checked headers, whose total work can't overflow
+    pub fn parent(left: &Node, right: &Node) -> Node {
+        Node::checked_parent(left, right).expect("work overflowing")
+    }
+
+    /// Parent of two nodes, None if their total work overflows
+    pub fn checked_parent(left: &Node, right: &Node) -> Option<Node> {
+        let work = left.work.checked_add(right.work)?;
+        let mut bytes = [0u8; 128];
+        bytes[..32].copy_from_slice(&left.hash);
+        bytes[32..64].copy_from_slice(&left.work.to_le_bytes());
+        bytes[64..96].copy_from_slice(&right.hash);
+        bytes[96..].copy_from_slice(&right.work.to_le_bytes());
+        Some(Node {
+            hash: sha256d(&bytes),
+            work,
+        })
+    }
+}
+
+/// Merkle mountain range of a chain of headers, see the module doc
+#[derive(Clone, Debug)]
+pub struct Mmr {
+    /// `levels[h][i]` is the root of the headers from `i << h` to `(i + 1) << h` excluded
+    levels: Vec<Vec<Node>>,
+}
+
+impl Mmr {
+    pub fn new() -> Mmr {
+        Mmr { levels: vec![Vec::new()] }
+    }
+
+    /// Range of the headers of `store`
+    pub fn from_store(store: &dyn HeaderStore) -> Mmr {
+        let mut mmr = Mmr::new();
+        for height in 0..store.len() {
+            match store.header_at(height) {
+                Some(block_header) => mmr.push(&block_header),
+                None => break,
+            }
+        }
+        mmr
+    }
+
+    /// Number of headers
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, block_header: &BlockHeader) {
+        self.levels[0].push(Node::leaf(block_header));
+        let mut height = 0;
+        while self.levels[height].len().is_multiple_of(2) {
+            let nodes = &self.levels[height];
+            let parent = Node::parent(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+            if self.levels.len() == height + 1 {
+                self.levels.push(Vec::new());
+            }
+            self.levels[height + 1].push(parent);
+            height += 1;
+        }
+    }
+
+    /// Keep the first `len` headers, after a reorg
+    pub fn truncate(&mut self, len: usize) {
+        for (height, nodes) in self.levels.iter_mut().enumerate() {
+            nodes.truncate(len >> height);
+        }
+    }
+
+    /// Roots of the perfect trees of the range, from the oldest, with their level and index
+    fn peaks(&self) -> Vec<(usize, usize)> {
+        let len = self.len();
+        (0..self.levels.len())
+            .rev()
+            .filter(|height| len >> height & 1 == 1)
+            .map(|height| (height, (len >> height) - 1))
+            .collect()
+    }
+
+    pub fn root(&self) -> Option<Node> {
+        let peaks: Vec<Node> = self.peaks().iter().map(|&(h, i)| self.levels[h][i]).collect();
+        bag(&peaks)
+    }
+
+    /// Index of the header containing the unit of work `point`, counted from genesis
+    fn find(&self, point: Uint256) -> Option<usize> {
+        let mut before = Uint256::zero();
+        for (height, index) in self.peaks() {
+            let peak = self.levels[height][index];
+            if point >= before + peak.work {
+                before = before + peak.work;
+                continue;
+            }
+            let mut index = index;
+            for height in (0..height).rev() {
+                let left = self.levels[height][index * 2];
+                index *= 2;
+                if point >= before + left.work {
+                    before = before + left.work;
+                    index += 1;
+                }
+            }
+            return Some(index);
+        }
+        None
+    }
+
+    /// FlyClient proof of the range with `samples` samples
+    pub fn prove(&self, store: &dyn HeaderStore, samples: usize) -> Option<Proof> {
+        let root = self.root()?;
+        let mut proof = Proof {
+            len: self.len(),
+            peaks: self.peaks().iter().map(|&(h, i)| self.levels[h][i]).collect(),
+            samples: Vec::new(),
+        };
+        let mut indexes: Vec<usize> = (0..samples).filter_map(|i| self.find(sample_point(&root, i))).collect();
+        indexes.push(self.len() - 1);
+        for index in indexes {
+            let siblings = (0..peak_height(self.len(), index))
+                .map(|height| self.levels[height][(index >> height) ^ 1])
+                .collect();
+            proof.samples.push(Sample {
+                index,
+                block_header: store.header_at(index)?,
+                siblings,
+            });
+        }
+        Some(proof)
+    }
+}
+
+impl Default for Mmr {
+    fn default() -> Mmr {
+        Mmr::new()
+    }
+}
+
+/// Level of the peak containing the header `index` in a range of `len` headers
+fn peak_height(len: usize, index: usize) -> usize {
+    let mut start = 0;
+    for height in (0..usize::BITS as usize).rev() {
+        if len >> height & 1 == 1 {
+            if index < start + (1 << height) {
+                return height;
+            }
+            start += 1 << height;
+        }
+    }
+    0
+}
+
+/// Root of the peaks, hashed from the newest, None if there are none or their work overflows
+fn bag(peaks: &[Node]) -> Option<Node> {
+    let (last, others) = peaks.split_last()?;
+    others.iter().rev().try_fold(*last, |acc, peak| Node::checked_parent(peak, &acc))
+}
+
+/// The unit of work sampled by sample `i` of the range with `root`, whose work isn't zero
+fn sample_point(root: &Node, i: usize) -> Uint256 {
+    let mut bytes = [0u8; 36];
+    bytes[..32].copy_from_slice(&root.hash);
+    bytes[32..].copy_from_slice(&(i as u32).to_le_bytes());
+    let random = sha256d(&bytes);
+    let binade = u32::from(random[0]) % (BINADES + 1);
+    let mut fraction = [0u8; 8];
+    fraction.copy_from_slice(&random[8..16]);
+    let fraction = u64::from_le_bytes(fraction);
+    // work from the tip, in [total >> (binade + 1), total >> binade), the last one down to 0
+    let (low, width) = match binade {
+        BINADES => (Uint256::zero(), root.work >> BINADES as usize),
+        _ => (root.work >> (binade as usize + 1), root.work >> (binade as usize + 1)),
+    };
+    // width * fraction / 2^64, splitting the low 64 bits of width to avoid overflowing
+    let high = (width >> 64).wrapping_mul_u64(fraction);
+    let low_bits = (width << 192 >> 192).wrapping_mul_u64(fraction) >> 64;
+    let last = root.work - Uint256::from_u64(1);
+    last - (low + high + low_bits).min(last)
+}
+
+/// A sampled header with the siblings from it up to its peak
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub index: usize,
+    pub block_header: BlockHeader,
+    pub siblings: Vec<Node>,
+}
+
+/// FlyClient proof, see the module doc
+#[derive(Clone, Debug)]
+pub struct Proof {
+    /// Number of headers
+    pub len: usize,
+    pub peaks: Vec<Node>,
+    pub samples: Vec<Sample>,
+}
+
+impl Proof {
+    /// Check the samples and their proof of work, returning the root with the proven work
+    pub fn verify(&self, samples: usize) -> Result<Node, &'static str> {
+        if self.peaks.len() != self.len.count_ones() as usize {
+            return Err("Peaks not matching the length");
+        }
+        let root = bag(&self.peaks).ok_or("Proof of an empty range or overflowing work")?;
+        if root.work.is_zero() {
+            return Err("Proof without work");
+        }
+        if self.samples.len() != samples + 1 || self.samples[samples].index != self.len - 1 {
+            return Err("Unexpected samples");
+        }
+        for (i, sample) in self.samples.iter().enumerate() {
+            if sample.index >= self.len {
+                return Err("Sample outside the range");
+            }
+            let height = peak_height(self.len, sample.index);
+            if sample.siblings.len() != height {
+                return Err("Path of unexpected length");
+            }
+            sample.block_header.check_pow()?;
+            let mut node = Node::leaf(&sample.block_header);
+            let mut before = Uint256::zero();
+            for (level, sibling) in sample.siblings.iter().enumerate() {
+                node = match (sample.index >> level) & 1 {
+                    0 => Node::checked_parent(&node, sibling),
+                    _ => {
+                        before = before.checked_add(sibling.work).ok_or(OVERFLOW)?;
+                        Node::checked_parent(sibling, &node)
+                    }
+                }
+                .ok_or(OVERFLOW)?;
+            }
+            // the bigger peaks come first
+            let peak = (self.len >> height >> 1).count_ones() as usize;
+            if self.peaks.get(peak) != Some(&node) {
+                return Err("Sample not in the range");
+            }
+            let before = self.peaks[..peak]
+                .iter()
+                .try_fold(before, |acc, p| acc.checked_add(p.work))
+                .ok_or(OVERFLOW)?;
+            let after = before.checked_add(sample.block_header.work()).ok_or(OVERFLOW)?;
+            if i < samples {
+                let point = sample_point(&root, i);
+                if point < before || point >= after {
+                    return Err("Sample not the one of the random point");
+                }
+            }
+        }
+        Ok(root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::stream;
+    use flyclient::{Mmr, Node};
+    use store::{HeaderStore, MemoryStore};
+    use util::uint::Uint256;
+
+    #[test]
+    pub fn test_mmr() {
+        let block_headers = stream::decode(include_bytes!("../examples/2016/0")).unwrap();
+        let store = MemoryStore::new();
+        store.extend(&block_headers[..1000]).unwrap();
+        let mut mmr = Mmr::from_store(&store);
+        assert_eq!(mmr.len(), 1000);
+        let total = block_headers[..1000].iter().fold(Uint256::zero(), |acc, h| acc + h.work());
+        assert_eq!(mmr.root().unwrap().work, total);
+        let leaves: Vec<Node> = block_headers[..3].iter().map(Node::leaf).collect();
+        let mut three = Mmr::new();
+        for block_header in &block_headers[..3] {
+            three.push(block_header);
+        }
+        let expected = Node::parent(&Node::parent(&leaves[0], &leaves[1]), &leaves[2]);
+        assert_eq!(three.root(), Some(expected));
+
+        for len in &[1000, 7, 2, 1] {
+            mmr.truncate(*len);
+            store.truncate(*len).unwrap();
+            let proof = mmr.prove(&store, 20).unwrap();
+            assert_eq!(proof.verify(20).unwrap(), mmr.root().unwrap());
+        }
+        store.extend(&block_headers[1..]).unwrap();
+        for block_header in &block_headers[1..] {
+            mmr.push(block_header);
+        }
+        let proof = mmr.prove(&store, 64).unwrap();
+        assert_eq!(proof.verify(64).unwrap().work, block_headers.iter().fold(Uint256::zero(), |acc, h| acc + h.work()));
+        // samples near the tip
+        assert!(proof.samples.iter().filter(|s| s.index >= 1008).count() > 32);
+        assert!(proof.verify(63).is_err());
+
+        let mut tampered = proof.clone();
+        tampered.samples[3].block_header.nonce = [0; 4];
+        assert!(tampered.verify(64).is_err());
+        let mut tampered = proof.clone();
+        tampered.samples[0].index ^= 1;
+        assert!(tampered.verify(64).is_err());
+        let mut tampered = proof.clone();
+        tampered.peaks[0].work = tampered.peaks[0].work + Uint256::from_u64(1);
+        assert!(tampered.verify(64).is_err());
+        // claimed work overflowing or zero
+        let mut tampered = proof.clone();
+        tampered.peaks[0].work = !Uint256::zero();
+        assert_eq!(tampered.verify(64), Err("Proof of an empty range or overflowing work"));
+        let mut tampered = proof.clone();
+        for peak in tampered.peaks.iter_mut() {
+            peak.work = Uint256::zero();
+        }
+        assert_eq!(tampered.verify(64), Err("Proof without work"));
+    }
+}
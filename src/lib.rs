@@ -1,6 +1,8 @@
+extern crate bytes;
 extern crate crypto;
 extern crate futures;
 extern crate hyper;
+extern crate memmap;
 extern crate serde;
 extern crate serde_json;
 extern crate tokio_core;
@@ -11,4 +13,10 @@ extern crate serde_derive;
 pub mod bitcoin;
 pub mod server;
 pub mod client;
+pub mod accumulator;
+pub mod attestation;
+pub mod flyclient;
+pub mod nipopow;
+pub mod spv;
+pub mod store;
 pub mod util;
@@ -0,0 +1,339 @@
+//! Non-interactive proofs of proof of work, see https://eprint.iacr.org/2017/963
+//!
+//! A header has level `μ` if its hash is at or below its target shifted right by `μ`
+//! bits: about half of the headers have level 1, a quarter level 2 and so on. The
+//! superchain of level `μ` is the subsequence of the headers of level at least `μ`.
+//! A proof is the last `k` headers, the suffix, preceded by the superchains needed to
+//! show the work of the chain with at least `m` headers on the highest levels, a few
+//! hundred headers for the whole mainnet chain.
+//!
+//! Bitcoin headers don't commit to previous superblocks (the interlink of the paper), so
+//! the linkage of the proof headers is checked only between consecutive heights and a
+//! verifier can compare the work of two proofs but can't tell the sparse superblocks
+//! of a proof are on a single chain.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::io;
+use bitcoin::header::BlockHeader;
+use store::HeaderStore;
+use util::uint::Uint256;
+
+/// Superblocks needed on a level before moving down to the next one
+pub const DEFAULT_M: usize = 15;
+
+/// Headers in the suffix of a proof
+pub const DEFAULT_K: usize = 6;
+
+/// Level of `block_header`, the bits of its hash below the target, see the module doc
+pub fn level(block_header: &BlockHeader) -> usize {
+    let hash = Uint256::from_le_bytes(block_header.hash());
+    let target = block_header.target();
+    let mut level = 0;
+    while level < 255 && hash <= target >> (level + 1) {
+        level += 1;
+    }
+    level
+}
+
+/// Superchains of the levels above 0 of a store, updated incrementally and after reorgs
+#[derive(Clone, Debug, Default)]
+pub struct Superchains {
+    /// `levels[i]` contains the height and hash of the headers of level greater than `i`
+    levels: Vec<Vec<(usize, [u8; 32])>>,
+    scanned: usize,
+    last_hash: Option<[u8; 32]>,
+}
+
+impl Superchains {
+    pub fn new() -> Superchains {
+        Superchains {
+            levels: Vec::new(),
+            scanned: 0,
+            last_hash: None,
+        }
+    }
+
+    /// Follow the headers of `store`
+    pub fn update(&mut self, store: &dyn HeaderStore) {
+        if self.scanned > 0 && store.hash_at(self.scanned - 1) != self.last_hash {
+            // the superblocks of level 1 are dense, rescan after the last still in the chain
+            let first = self.levels.first().cloned().unwrap_or_default();
+            let valid = first
+                .iter()
+                .rev()
+                .find(|&&(height, hash)| store.hash_at(height) == Some(hash))
+                .map_or(0, |&(height, _)| height + 1);
+            for superchain in self.levels.iter_mut() {
+                superchain.retain(|&(height, _)| height < valid);
+            }
+            self.levels.retain(|superchain| !superchain.is_empty());
+            self.scanned = valid;
+        }
+        for height in self.scanned..store.len() {
+            let block_header = match store.header_at(height) {
+                Some(block_header) => block_header,
+                None => break,
+            };
+            let hash = block_header.hash();
+            let level = level(&block_header);
+            while self.levels.len() < level {
+                self.levels.push(Vec::new());
+            }
+            for superchain in self.levels[..level].iter_mut() {
+                superchain.push((height, hash));
+            }
+            self.scanned = height + 1;
+            self.last_hash = Some(hash);
+        }
+    }
+
+    /// Highest level with at least a header
+    pub fn max_level(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Heights from `start` to `end` excluded of the headers of level at least `level`
+    fn heights(&self, level: usize, start: usize, end: usize) -> Vec<usize> {
+        match level {
+            0 => (start..end).collect(),
+            _ => self.levels[level - 1]
+                .iter()
+                .map(|&(height, _)| height)
+                .filter(|&height| height >= start && height < end)
+                .collect(),
+        }
+    }
+
+    /// Proof of the chain of `store`, `m` and `k` as described in the module doc
+    pub fn prove(&self, store: &dyn HeaderStore, m: usize, k: usize) -> Option<Proof> {
+        let len = store.len().min(self.scanned);
+        if len <= k || m == 0 {
+            return None;
+        }
+        let suffix_height = len - k;
+        let mut heights = vec![0];
+        let mut start = 0;
+        for level in (0..=self.max_level()).rev() {
+            let superchain = self.heights(level, start, suffix_height);
+            heights.extend_from_slice(&superchain);
+            if level > 0 && superchain.len() >= m {
+                start = superchain[superchain.len() - m];
+            }
+        }
+        heights.sort_unstable();
+        heights.dedup();
+        let headers = heights
+            .into_iter()
+            .map(|height| store.header_at(height).map(|header| (height, header)))
+            .collect::<Option<Vec<_>>>()?;
+        let suffix = (suffix_height..len)
+            .map(|height| store.header_at(height))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Proof {
+            headers,
+            suffix_height,
+            suffix,
+        })
+    }
+}
+
+/// Proof of the work of a chain, see the module doc
+#[derive(Clone, Debug)]
+pub struct Proof {
+    /// Superchain headers before the suffix, with their height, starting with the genesis
+    pub headers: Vec<(usize, BlockHeader)>,
+    pub suffix_height: usize,
+    pub suffix: Vec<BlockHeader>,
+}
+
+impl Proof {
+    /// All the headers of the proof with their height
+    fn all(&self) -> Vec<(usize, BlockHeader)> {
+        let mut all = self.headers.clone();
+        all.extend(self.suffix.iter().enumerate().map(|(i, h)| (self.suffix_height + i, *h)));
+        all
+    }
+
+    /// Check the structure of the proof, the proof of work of its headers and the linkage
+    /// of the headers at consecutive heights
+    pub fn verify(&self, k: usize) -> Result<(), &'static str> {
+        if self.suffix.len() != k {
+            return Err("Suffix of unexpected length");
+        }
+        match self.headers.first() {
+            Some(&(0, ref genesis)) if genesis.hash() == BlockHeader::genesis().hash() => (),
+            _ => return Err("Proof not starting with the genesis"),
+        }
+        let all = self.all();
+        for pair in all.windows(2) {
+            let (ref prev, ref next) = (pair[0], pair[1]);
+            if next.0 <= prev.0 {
+                return Err("Headers not in height order");
+            }
+            if next.0 == prev.0 + 1 && next.1.prev_blockhash != prev.1.hash() {
+                return Err("Consecutive headers not linked");
+            }
+        }
+        if self.headers[self.headers.len() - 1].0 + 1 != self.suffix_height {
+            return Err("Suffix not following the superchains");
+        }
+        for (_, block_header) in all.iter().skip(1) {
+            block_header.check_pow()?;
+        }
+        Ok(())
+    }
+
+    /// Compare the work proven by two valid proofs, as the best argument of the paper after
+    /// the last header in common, weighting every header by its work
+    pub fn compare(&self, other: &Proof, m: usize) -> Ordering {
+        let (mine, theirs) = (self.all(), other.all());
+        let common: HashSet<(usize, [u8; 32])> = theirs.iter().map(|&(h, ref t)| (h, t.hash())).collect();
+        let fork = mine
+            .iter()
+            .filter(|&&(height, ref header)| common.contains(&(height, header.hash())))
+            .map(|&(height, _)| height)
+            .max()
+            .unwrap_or(0);
+        best_arg(&mine, fork, m).cmp(&best_arg(&theirs, fork, m))
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + 84 * self.headers.len() + 80 * self.suffix.len());
+        bytes.extend_from_slice(&(self.headers.len() as u32).to_le_bytes());
+        for &(height, ref block_header) in self.headers.iter() {
+            bytes.extend_from_slice(&(height as u32).to_le_bytes());
+            bytes.extend_from_slice(&block_header.as_bytes());
+        }
+        bytes.extend_from_slice(&(self.suffix_height as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.suffix.len() as u32).to_le_bytes());
+        for block_header in self.suffix.iter() {
+            bytes.extend_from_slice(&block_header.as_bytes());
+        }
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Proof> {
+        let mut pos = 0;
+        let count = read_u32(bytes, &mut pos)?;
+        let mut headers = Vec::new();
+        for _ in 0..count {
+            let height = read_u32(bytes, &mut pos)?;
+            headers.push((height, read_header(bytes, &mut pos)?));
+        }
+        let suffix_height = read_u32(bytes, &mut pos)?;
+        let count = read_u32(bytes, &mut pos)?;
+        let mut suffix = Vec::new();
+        for _ in 0..count {
+            suffix.push(read_header(bytes, &mut pos)?);
+        }
+        if pos != bytes.len() {
+            return Err(invalid_data("trailing bytes after the proof"));
+        }
+        Ok(Proof {
+            headers,
+            suffix_height,
+            suffix,
+        })
+    }
+}
+
+/// Highest work of the superchains after `fork` of a level with at least `m` headers,
+/// multiplied by two to the level
+fn best_arg(headers: &[(usize, BlockHeader)], fork: usize, m: usize) -> Uint256 {
+    let after: Vec<(usize, &BlockHeader)> = headers
+        .iter()
+        .filter(|&&(height, _)| height > fork)
+        .map(|(_, header)| (level(header), header))
+        .collect();
+    let max_level = after.iter().map(|&(level, _)| level).max().unwrap_or(0);
+    (0..=max_level)
+        .filter_map(|mu| {
+            let superchain: Vec<&BlockHeader> = after.iter().filter(|&&(l, _)| l >= mu).map(|&(_, h)| h).collect();
+            match mu == 0 || superchain.len() >= m {
+                true => Some(superchain.iter().fold(Uint256::zero(), |acc, h| acc + h.work()) << mu),
+                false => None,
+            }
+        })
+        .max()
+        .unwrap_or_else(Uint256::zero)
+}
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> io::Result<usize> {
+    if bytes.len() < *pos + 4 {
+        return Err(invalid_data("proof truncated"));
+    }
+    *pos += 4;
+    Ok(u32::from_le_bytes([bytes[*pos - 4], bytes[*pos - 3], bytes[*pos - 2], bytes[*pos - 1]]) as usize)
+}
+
+fn read_header(bytes: &[u8], pos: &mut usize) -> io::Result<BlockHeader> {
+    if bytes.len() < *pos + 80 {
+        return Err(invalid_data("proof truncated"));
+    }
+    let mut header_bytes = [0u8; 80];
+    header_bytes.copy_from_slice(&bytes[*pos..*pos + 80]);
+    *pos += 80;
+    Ok(BlockHeader::from_bytes(header_bytes))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::stream;
+    use nipopow;
+    use nipopow::{Proof, Superchains};
+    use std::cmp::Ordering;
+    use store::{HeaderStore, MemoryStore};
+
+    #[test]
+    pub fn test_nipopow() {
+        let block_headers = stream::decode(include_bytes!("../examples/2016/0")).unwrap();
+        let levels: Vec<usize> = block_headers.iter().map(nipopow::level).collect();
+        assert!(levels.iter().filter(|&&level| level >= 1).count() > 800);
+        assert!(levels.iter().filter(|&&level| level >= 2).count() < 700);
+
+        let store = MemoryStore::new();
+        store.extend(&block_headers).unwrap();
+        let mut superchains = Superchains::new();
+        superchains.update(&store);
+        let proof = superchains.prove(&store, 5, 6).unwrap();
+        assert!(proof.headers.len() < 200);
+        assert_eq!(proof.suffix_height, 2010);
+        assert!(proof.verify(6).is_ok());
+        assert!(proof.verify(5).is_err());
+        let parsed = Proof::parse(&proof.as_bytes()).unwrap();
+        assert_eq!(parsed.as_bytes(), proof.as_bytes());
+        assert!(Proof::parse(&proof.as_bytes()[1..]).is_err());
+
+        let mut tampered = proof.clone();
+        tampered.suffix[3].nonce = [0; 4];
+        assert!(tampered.verify(6).is_err());
+        let mut tampered = proof.clone();
+        tampered.headers.remove(0);
+        assert!(tampered.verify(6).is_err());
+
+        // a shorter chain proves less work
+        store.truncate(1500).unwrap();
+        superchains.update(&store);
+        let shorter = superchains.prove(&store, 5, 6).unwrap();
+        assert!(shorter.verify(6).is_ok());
+        assert_eq!(proof.compare(&shorter, 5), Ordering::Greater);
+        assert_eq!(shorter.compare(&proof, 5), Ordering::Less);
+        assert_eq!(proof.compare(&proof, 5), Ordering::Equal);
+
+        // a reorg replaces the superblocks after the fork
+        let mut fork = block_headers[1500];
+        fork.nonce = [0; 4];
+        store.extend(&[fork]).unwrap();
+        superchains.update(&store);
+        store.truncate(1500).unwrap();
+        store.extend(&block_headers[1500..]).unwrap();
+        superchains.update(&store);
+        assert_eq!(superchains.prove(&store, 5, 6).unwrap().as_bytes(), proof.as_bytes());
+    }
+}
@@ -1,25 +1,123 @@
-use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
+use std::vec;
 use hyper;
 use futures;
+use futures::stream::IterOk;
 use hyper::header::{AcceptRanges, ByteRangeSpec, ContentLength, ContentType, Headers, Range,
                     RangeUnit};
 use hyper::server::{Http, Request, Response, Service};
 use std::net::SocketAddr;
 use hyper::StatusCode;
+use serde::Serialize;
+use serde_json;
+use accumulator;
+use accumulator::Accumulator;
+use attestation::{Attestation, Signer};
+use nipopow;
+use nipopow::Superchains;
+use bitcoin::Config;
+use bitcoin::envelope;
+use bitcoin::header::BlockHeader;
+use bitcoin::merkle::TxOutProof;
+use bitcoin::stream;
+use bitcoin::stream::ChunkSize;
+use spv;
+use store;
+use store::HeaderStore;
+use store::slice::Slice;
+use util::hex::{FromHex, ToHex};
+
+/// Header of the `/bitcoin-headers` responses with the chunk size of the stream
+pub const CHUNK_SIZE_HEADER: &str = "X-Chunk-Size";
+
+/// Response body streaming the slices of the store without copying them
+type Body = IterOk<vec::IntoIter<Slice>, hyper::Error>;
+
+/// The endpoints served
+enum Endpoint {
+    Headers(Option<Range>),
+    Envelope(Option<String>),
+    BlockHeight(String),
+    HeightAtTime(String),
+    TxOutProof(String),
+    PeriodRoots,
+    HeaderProof(String),
+    Nipopow(Option<String>),
+    Attestation,
+}
+
+#[derive(Serialize)]
+struct BlockHeightResponse {
+    hash: String,
+    height: usize,
+}
+
+#[derive(Serialize)]
+struct HeightAtTimeResponse {
+    time: u32,
+    height: usize,
+    median_time_past: u32,
+}
+
+#[derive(Serialize)]
+struct TxOutProofResponse {
+    block_hash: String,
+    height: usize,
+    confirmations: usize,
+    txids: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PeriodRootsResponse {
+    periods: usize,
+    root: String,
+    period_roots: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct HeaderProofResponse {
+    height: usize,
+    periods: usize,
+    header: String,
+    period_root: String,
+    period_branch: Vec<String>,
+    root: String,
+    roots_branch: Vec<String>,
+}
 
 #[derive(Clone)]
 struct HeaderServices {
-    block_headers_bytes: Arc<Mutex<Vec<u8>>>,
+    store: Arc<dyn HeaderStore>,
+    network: [u8; 4],
+    chunk_size: ChunkSize,
+    accumulator: Arc<RwLock<Accumulator>>,
+    superchains: Arc<Mutex<Superchains>>,
+    signer: Option<Arc<Signer>>,
 }
 
-pub fn start(block_headers_bytes: Arc<Mutex<Vec<u8>>>) {
+/// Serve `store`, with the period roots of `accumulator`, kept up to date by the sync
+pub fn start(store: Arc<dyn HeaderStore>, config: &Config, accumulator: Arc<RwLock<Accumulator>>) {
+    let network = envelope::network_magic(config.network()).unwrap();
+    let chunk_size = config.chunk_size();
+    let superchains = Arc::new(Mutex::new(Superchains::new()));
+    let signer = config.signing_key().map(|seed| Arc::new(Signer::new(seed)));
+    if let Some(ref signer) = signer {
+        println!("signing the tips with the public key {}", signer.public_key().to_hex());
+    }
     let x = "0.0.0.0:3000";
     println!("server starting at http://{}", x);
     let addr: SocketAddr = x.parse().unwrap();
     let server = Http::new()
         .bind(&addr, move || {
             Ok(HeaderServices {
-                block_headers_bytes: block_headers_bytes.clone(),
+                store: store.clone(),
+                network,
+                chunk_size,
+                accumulator: accumulator.clone(),
+                superchains: superchains.clone(),
+                signer: signer.clone(),
             })
         })
         .unwrap();
@@ -28,64 +126,251 @@ pub fn start(block_headers_bytes: Arc<Mutex<Vec<u8>>>) {
 
 impl Service for HeaderServices {
     type Request = Request;
-    type Response = Response;
+    type Response = Response<Body>;
     type Error = hyper::Error;
     type Future = futures::future::FutureResult<Self::Response, Self::Error>;
 
     fn call(&self, _req: Request) -> Self::Future {
         let response = match validate_req(_req) {
             Err(e) => Response::new().with_status(e),
-            Ok(r) => build_range_response(self.block_headers_bytes.clone(), r),
+            Ok(Endpoint::Headers(r)) => build_range_response(&*self.store, self.chunk_size, r),
+            Ok(Endpoint::Envelope(query)) => build_envelope_response(
+                &*self.store,
+                self.network,
+                self.chunk_size,
+                query.as_deref(),
+            ),
+            Ok(Endpoint::BlockHeight(hash)) => build_height_response(&*self.store, &hash),
+            Ok(Endpoint::HeightAtTime(time)) => build_height_at_time_response(&*self.store, &time),
+            Ok(Endpoint::TxOutProof(proof)) => build_txout_proof_response(&*self.store, &proof),
+            Ok(Endpoint::PeriodRoots) => build_period_roots_response(&*self.store, &self.accumulator),
+            Ok(Endpoint::HeaderProof(height)) => {
+                build_header_proof_response(&*self.store, &self.accumulator, &height)
+            }
+            Ok(Endpoint::Attestation) => match self.signer {
+                Some(ref signer) => build_attestation_response(self, signer),
+                None => Response::new().with_status(StatusCode::NotFound),
+            },
+            Ok(Endpoint::Nipopow(query)) => {
+                build_nipopow_response(&*self.store, &self.superchains, query.as_deref())
+            }
         };
         futures::future::ok(response)
     }
 }
 
-fn validate_req(_req: Request) -> Result<Option<Range>, StatusCode> {
+fn validate_req(_req: Request) -> Result<Endpoint, StatusCode> {
     let uri_path = _req.uri().path();
 
+    if uri_path == "/bitcoin-headers/attestation" {
+        return Ok(Endpoint::Attestation);
+    }
+    if uri_path == "/bitcoin-headers/envelope" {
+        return Ok(Endpoint::Envelope(_req.uri().query().map(String::from)));
+    }
+    if let Some(hash) = uri_path.strip_prefix("/block-height/") {
+        return Ok(Endpoint::BlockHeight(String::from(hash)));
+    }
+    if let Some(time) = uri_path.strip_prefix("/height-at-time/") {
+        return Ok(Endpoint::HeightAtTime(String::from(time)));
+    }
+    if uri_path == "/nipopow" {
+        return Ok(Endpoint::Nipopow(_req.uri().query().map(String::from)));
+    }
+    if uri_path == "/period-roots" {
+        return Ok(Endpoint::PeriodRoots);
+    }
+    if let Some(height) = uri_path.strip_prefix("/header-proof/") {
+        return Ok(Endpoint::HeaderProof(String::from(height)));
+    }
+    if let Some(proof) = uri_path.strip_prefix("/txout-proof/") {
+        return Ok(Endpoint::TxOutProof(String::from(proof)));
+    }
     match uri_path.eq("/bitcoin-headers") {
         true => match _req.headers().get::<Range>() {
-            Some(r) => Ok(Some(r.clone())),
-            None => Ok(None),
+            Some(r) => Ok(Endpoint::Headers(Some(r.clone()))),
+            None => Ok(Endpoint::Headers(None)),
         },
         false => Err(StatusCode::NotFound),
     }
 }
 
+fn json_response<T: Serialize>(value: &T) -> Response<Body> {
+    let bytes = serde_json::to_vec(value).unwrap();
+    Response::new()
+        .with_header(ContentType::json())
+        .with_header(ContentLength(bytes.len() as u64))
+        .with_body(futures::stream::iter_ok(vec![Slice::from(bytes)]))
+}
+
+/// Height in the best chain of the block with hash `hash_hex`, in the usual big endian hex
+fn build_height_response(store: &dyn HeaderStore, hash_hex: &str) -> Response<Body> {
+    let mut hash = [0u8; 32];
+    match hash_hex.from_hex() {
+        Ok(ref bytes) if bytes.len() == 32 => hash.copy_from_slice(bytes),
+        _ => return Response::new().with_status(StatusCode::BadRequest),
+    }
+    hash.reverse();
+    match store.height_of(&hash) {
+        Some(height) => json_response(&BlockHeightResponse {
+            hash: String::from(hash_hex),
+            height,
+        }),
+        None => Response::new().with_status(StatusCode::NotFound),
+    }
+}
+
+
+/// Take the first `len` bytes of `slices`, splitting the slice they end in
+fn take(slices: &mut VecDeque<Slice>, len: usize) -> io::Result<Vec<Slice>> {
+    let mut result = Vec::new();
+    let mut remaining = len;
+    while remaining > 0 {
+        let mut slice = match slices.pop_front() {
+            Some(slice) => slice,
+            None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "range outside the stream")),
+        };
+        if slice.len() > remaining {
+            slices.push_front(slice.split_off(remaining));
+        }
+        remaining -= slice.len();
+        result.push(slice);
+    }
+    Ok(result)
+}
+
+/// The headers `first..last` of the stream with `chunk_size`, the slices of the stored stream
+/// with the full headers starting the shorter chunks between them
+fn rebuild_slices(
+    store: &dyn HeaderStore,
+    chunk_size: ChunkSize,
+    first: usize,
+    last: usize,
+) -> io::Result<VecDeque<Slice>> {
+    // the stored chunk is read from its full header, having the bits of the chunk
+    let chunk_start = first - first % stream::CHUNK_SIZE;
+    let stored = store.read_slices(stream::offset(chunk_start), stream::offset(last))?;
+    let mut stored: VecDeque<Slice> = stored.into_iter().collect();
+    let mut result = VecDeque::new();
+    let mut bits = [0u8; 4];
+    // length of the stored headers not taken yet
+    let mut run = 0;
+    for height in chunk_start..last {
+        if height == first {
+            take(&mut stored, run)?;
+            run = 0;
+        }
+        if stream::is_full(height) {
+            result.extend(take(&mut stored, run)?);
+            run = 0;
+            let mut header_bytes = [0u8; 80];
+            header_bytes.copy_from_slice(&take(&mut stored, 80)?.concat());
+            bits = BlockHeader::from_bytes(header_bytes).bits;
+            if height >= first {
+                result.push_back(Slice::from(&header_bytes[..]));
+            }
+        } else if chunk_size.is_full(height) && height >= first {
+            result.extend(take(&mut stored, run)?);
+            run = 0;
+            let mut compressed_bytes = [0u8; 44];
+            compressed_bytes.copy_from_slice(&take(&mut stored, 44)?.concat());
+            let prev_hash = match store.hash_at(height - 1) {
+                Some(prev_hash) => prev_hash,
+                None => return Err(io::Error::new(io::ErrorKind::NotFound, "header removed")),
+            };
+            let block_header = BlockHeader::from_compressed_bytes(compressed_bytes, prev_hash, bits);
+            result.push_back(Slice::from(&block_header.as_bytes()[..]));
+        } else {
+            run += stream::header_len(height);
+        }
+    }
+    result.extend(take(&mut stored, run)?);
+    Ok(result)
+}
+
+/// The bytes `start..end` of the stream with `chunk_size`. The stored stream has the default
+/// chunk size, the others are rebuilt adding the full headers starting the shorter chunks,
+/// without copying the stored slices and from a single chain if a reorg happens meanwhile
+fn read_slices(
+    store: &dyn HeaderStore,
+    chunk_size: ChunkSize,
+    start: usize,
+    end: usize,
+) -> io::Result<Vec<Slice>> {
+    if chunk_size == stream::DEFAULT {
+        return store.read_slices(start, end);
+    }
+    if start > end || end > chunk_size.byte_len(store.len()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "range outside the stream"));
+    }
+    if start == end {
+        return Ok(Vec::new());
+    }
+    let first = chunk_size.height_at(start).0;
+    let last = chunk_size.height_at(end - 1).0 + 1;
+    let mut slices = store::read_consistent(store, || rebuild_slices(store, chunk_size, first, last))?;
+    take(&mut slices, start - chunk_size.offset(first))?;
+    take(&mut slices, end - start)
+}
+
+/// The stream with `chunk_size` from `height`, which must start a chunk, to the tip, with
+/// the trailer of every complete chunk, see `bitcoin::trailer`
+fn read_trailers(store: &dyn HeaderStore, chunk_size: ChunkSize, height: usize) -> io::Result<Vec<Slice>> {
+    store::read_consistent(store, || {
+        let len = store.len();
+        let slices = read_slices(store, chunk_size, chunk_size.offset(height), chunk_size.byte_len(len))?;
+        let mut slices: VecDeque<Slice> = slices.into_iter().collect();
+        let mut result = Vec::new();
+        for chunk_start in (height..len).step_by(chunk_size.get()) {
+            let chunk_end = len.min(chunk_start + chunk_size.get());
+            result.extend(take(&mut slices, chunk_size.range_len(chunk_start, chunk_end))?);
+            if chunk_end - chunk_start == chunk_size.get() {
+                match store.hash_at(chunk_end - 1) {
+                    Some(hash) => result.push(Slice::from(&hash[..])),
+                    None => return Err(io::Error::new(io::ErrorKind::NotFound, "header removed")),
+                }
+            }
+        }
+        Ok(result)
+    })
+}
 
 fn build_range_response(
-    block_headers_bytes_arc: Arc<Mutex<Vec<u8>>>,
+    store: &dyn HeaderStore,
+    chunk_size: ChunkSize,
     range: Option<Range>,
-) -> Response {
-    let block_headers_bytes = block_headers_bytes_arc.lock().unwrap();
+) -> Response<Body> {
+    let byte_len = chunk_size.byte_len(store.len());
     match range {
         Some(range) => match range {
             Range::Bytes(r) => {
                 let (start, end) = match r[0] {
-                    ByteRangeSpec::AllFrom(start) => (start as usize, block_headers_bytes.len()),
+                    ByteRangeSpec::AllFrom(start) => (start as usize, byte_len),
                     ByteRangeSpec::FromTo(start, end) => (start as usize, end as usize),
-                    ByteRangeSpec::Last(x) => {
-                        let end = block_headers_bytes.len();
-                        (end - (x as usize), end)
-                    }
+                    ByteRangeSpec::Last(x) => (byte_len.saturating_sub(x as usize), byte_len),
                 };
                 println!("Range request {}-{}", start, end);
 
-                let mut reply = Vec::with_capacity(end - start);
-                reply.extend(&block_headers_bytes[start..end]);
+                let slices = match read_slices(store, chunk_size, start, end) {
+                    Ok(slices) => slices,
+                    Err(_) => return Response::new().with_status(StatusCode::RangeNotSatisfiable),
+                };
+                let mut headers = Headers::new();
+                headers.set(ContentType::octet_stream());
+                headers.set(ContentLength((end - start) as u64));
+                headers.set_raw(CHUNK_SIZE_HEADER, chunk_size.get().to_string());
 
                 Response::new()
-                    .with_header(ContentType::octet_stream())
-                    .with_header(ContentLength(reply.len() as u64))
-                    .with_body(reply)
+                    .with_headers(headers)
+                    .with_body(futures::stream::iter_ok(slices))
             }
             Range::Unregistered(_, _) => Response::new().with_status(StatusCode::NotFound),
         },
         None => {
             let mut headers = Headers::new();
             headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
-            headers.set(ContentLength(block_headers_bytes.len() as u64));
+            headers.set(ContentLength(byte_len as u64));
+            headers.set_raw(CHUNK_SIZE_HEADER, chunk_size.get().to_string());
 
             Response::new()
                 .with_headers(headers)
@@ -93,3 +378,237 @@ fn build_range_response(
         }
     }
 }
+
+/// First height with median time past at or after the unix time `time`
+fn build_height_at_time_response(store: &dyn HeaderStore, time: &str) -> Response<Body> {
+    let time: u32 = match time.parse() {
+        Ok(time) => time,
+        Err(_) => return Response::new().with_status(StatusCode::BadRequest),
+    };
+    let height = store.height_at_time(time);
+    match height.and_then(|height| store.median_time_past(height).map(|mtp| (height, mtp))) {
+        Some((height, median_time_past)) => json_response(&HeightAtTimeResponse {
+            time,
+            height,
+            median_time_past,
+        }),
+        None => Response::new().with_status(StatusCode::NotFound),
+    }
+}
+
+/// Check the `gettxoutproof` proof `proof_hex` against the served chain
+fn build_txout_proof_response(store: &dyn HeaderStore, proof_hex: &str) -> Response<Body> {
+    let proof = match proof_hex.from_hex() {
+        Ok(bytes) => TxOutProof::parse(&bytes),
+        Err(_) => return Response::new().with_status(StatusCode::BadRequest),
+    };
+    let proof = match proof.and_then(|proof| proof.matches().map(|_| proof)) {
+        Ok(proof) => proof,
+        Err(_) => return Response::new().with_status(StatusCode::BadRequest),
+    };
+    let reversed = |hash: &[u8; 32]| {
+        let mut hash = *hash;
+        hash.reverse();
+        hash.to_hex()
+    };
+    match spv::verify_txout_proof(store, &proof) {
+        Ok(inclusion) => json_response(&TxOutProofResponse {
+            block_hash: reversed(&inclusion.block_hash),
+            height: inclusion.height,
+            confirmations: inclusion.confirmations,
+            txids: inclusion.txids.iter().map(reversed).collect(),
+        }),
+        Err(_) => Response::new().with_status(StatusCode::NotFound),
+    }
+}
+
+/// A copy of the accumulator kept by the sync, following the headers appended since its
+/// last update, at most a batch, so the whole store is never hashed here
+fn accumulator(shared: &RwLock<Accumulator>, store: &dyn HeaderStore) -> Accumulator {
+    let mut accumulator = shared.read().unwrap().clone();
+    accumulator.update(store);
+    accumulator
+}
+
+/// Roots of the completed periods and the root over them
+fn build_period_roots_response(store: &dyn HeaderStore, shared: &RwLock<Accumulator>) -> Response<Body> {
+    let accumulator = accumulator(shared, store);
+    let period_roots = accumulator.period_roots();
+    json_response(&PeriodRootsResponse {
+        periods: period_roots.len(),
+        root: accumulator.root().to_hex(),
+        period_roots: period_roots.iter().map(|root| root.to_hex()).collect(),
+    })
+}
+
+/// The header at `height` with the proof it's in its period and under the root of roots
+fn build_header_proof_response(
+    store: &dyn HeaderStore,
+    shared: &RwLock<Accumulator>,
+    height: &str,
+) -> Response<Body> {
+    let height: usize = match height.parse() {
+        Ok(height) => height,
+        Err(_) => return Response::new().with_status(StatusCode::BadRequest),
+    };
+    let accumulator = accumulator(shared, store);
+    let proof = accumulator.prove(store, height);
+    match proof.and_then(|proof| store.header_at(height).map(|header| (proof, header))) {
+        Some((proof, block_header)) => json_response(&HeaderProofResponse {
+            height,
+            periods: proof.periods,
+            header: block_header.as_bytes().to_hex(),
+            period_root: proof.period_root.to_hex(),
+            period_branch: proof.period_branch.iter().map(|hash| hash.to_hex()).collect(),
+            root: accumulator.root().to_hex(),
+            roots_branch: proof.roots_branch.iter().map(|hash| hash.to_hex()).collect(),
+        }),
+        None => Response::new().with_status(StatusCode::NotFound),
+    }
+}
+
+/// The tip of the served stream signed by `signer`
+fn build_attestation_response(services: &HeaderServices, signer: &Signer) -> Response<Body> {
+    let store = &*services.store;
+    let mut accumulator = accumulator(&services.accumulator, store);
+    // the tip and the period roots of the same chain, even if a reorg happens meanwhile
+    let tip = store::read_consistent(store, || {
+        let len = store.len();
+        let tip_hash = store.hash_at(len.checked_sub(1)?)?;
+        accumulator.update(store);
+        // the store could have grown after reading the tip
+        let period_roots = accumulator.period_roots();
+        let periods = period_roots.len().min(len / stream::CHUNK_SIZE);
+        Some((len, tip_hash, accumulator::root(&period_roots[..periods])))
+    });
+    let (len, tip_hash, period_roots_root) = match tip {
+        Some(tip) => tip,
+        None => return Response::new().with_status(StatusCode::NotFound),
+    };
+    let attestation = Attestation {
+        network: services.network,
+        chunk_size: services.chunk_size.get() as u32,
+        height: (len - 1) as u32,
+        tip_hash,
+        stream_len: services.chunk_size.byte_len(len) as u64,
+        period_roots_root,
+    };
+    let bytes = signer.sign(&attestation).as_bytes().to_vec();
+    Response::new()
+        .with_header(ContentType::octet_stream())
+        .with_header(ContentLength(bytes.len() as u64))
+        .with_body(futures::stream::iter_ok(vec![Slice::from(bytes)]))
+}
+
+/// Value of the query parameter `name`, `default` if missing
+fn query_param(query: Option<&str>, name: &str, default: usize) -> Result<usize, StatusCode> {
+    let prefix = format!("{}=", name);
+    match query.unwrap_or("").split('&').filter_map(|p| p.strip_prefix(&prefix[..])).next() {
+        Some(value) => value.parse().map_err(|_| StatusCode::BadRequest),
+        None => Ok(default),
+    }
+}
+
+/// Proof of proof of work of the served chain, with the `m` and `k` query parameters
+fn build_nipopow_response(
+    store: &dyn HeaderStore,
+    superchains: &Mutex<Superchains>,
+    query: Option<&str>,
+) -> Response<Body> {
+    let params = query_param(query, "m", nipopow::DEFAULT_M)
+        .and_then(|m| query_param(query, "k", nipopow::DEFAULT_K).map(|k| (m, k)));
+    let (m, k) = match params {
+        Ok(params) => params,
+        Err(status) => return Response::new().with_status(status),
+    };
+    let mut superchains = superchains.lock().unwrap();
+    superchains.update(store);
+    match superchains.prove(store, m, k) {
+        Some(proof) => {
+            let bytes = proof.as_bytes();
+            Response::new()
+                .with_header(ContentType::octet_stream())
+                .with_header(ContentLength(bytes.len() as u64))
+                .with_body(futures::stream::iter_ok(vec![Slice::from(bytes)]))
+        }
+        None => Response::new().with_status(StatusCode::NotFound),
+    }
+}
+
+/// The stream in an envelope, from the height in the `from` query parameter or from genesis,
+/// with the trailers of the complete chunks if the `trailers` query parameter is 1
+fn build_envelope_response(
+    store: &dyn HeaderStore,
+    network: [u8; 4],
+    chunk_size: ChunkSize,
+    query: Option<&str>,
+) -> Response<Body> {
+    let params = query_param(query, "from", 0)
+        .and_then(|height| query_param(query, "trailers", 0).map(|trailers| (height, trailers)));
+    let (height, trailers) = match params {
+        Ok((height, 0)) => (height, false),
+        Ok((height, 1)) if chunk_size.is_full(height) => (height, true),
+        _ => return Response::new().with_status(StatusCode::BadRequest),
+    };
+    let mut envelope = envelope::Envelope::new(network, chunk_size, height);
+    let slices = match trailers {
+        true => {
+            envelope = envelope.trailers();
+            read_trailers(store, chunk_size, height)
+        }
+        false => read_slices(store, chunk_size, chunk_size.offset(height), chunk_size.byte_len(store.len())),
+    };
+    let slices = match slices {
+        Ok(slices) => slices,
+        Err(_) => return Response::new().with_status(StatusCode::NotFound),
+    };
+    let mut chunks = vec![Slice::from(envelope.as_bytes().to_vec())];
+    chunks.extend(slices);
+    let len: usize = chunks.iter().map(|chunk| chunk.len()).sum();
+
+    Response::new()
+        .with_header(ContentType::octet_stream())
+        .with_header(ContentLength(len as u64))
+        .with_body(futures::stream::iter_ok(chunks))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::stream;
+    use bitcoin::stream::ChunkSize;
+    use bitcoin::trailer;
+    use server::{read_slices, read_trailers};
+    use store::{HeaderStore, MemoryStore};
+
+    #[test]
+    pub fn test_read_slices_chunk_size() {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let mut block_headers = stream::decode(&test_data).unwrap();
+        let store = MemoryStore::new();
+        store.extend(&block_headers).unwrap();
+        let slices = read_slices(&store, stream::DEFAULT, 0, test_data.len()).unwrap();
+        assert_eq!(slices.concat(), test_data);
+
+        // ranges across the stored chunks
+        while block_headers.len() < 2 * 2016 + 10 {
+            let mut block_header = block_headers[block_headers.len() - 1];
+            block_header.prev_blockhash = block_header.hash();
+            block_headers.push(block_header);
+        }
+        store.extend(&block_headers[2016..]).unwrap();
+        let chunk_size = ChunkSize::new(144).unwrap();
+        let expected = chunk_size.encode(&block_headers);
+        let ranges = [(0, expected.len()), (100, 7000), (6372, 6452), (10, 10), (90000, 170000)];
+        for &(start, end) in ranges.iter() {
+            let slices = read_slices(&store, chunk_size, start, end).unwrap();
+            assert_eq!(slices.concat(), &expected[start..end]);
+        }
+        assert!(read_slices(&store, chunk_size, 0, expected.len() + 1).is_err());
+
+        for &(chunk_size, height) in &[(chunk_size, 0), (chunk_size, 2880), (stream::DEFAULT, 2016)] {
+            let expected = trailer::encode_from(&block_headers[height..], height, chunk_size);
+            assert_eq!(read_trailers(&store, chunk_size, height).unwrap().concat(), expected);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Simplified payment verification: proofs that transactions are in a block of the stored chain
+
+use bitcoin::merkle;
+use bitcoin::merkle::TxOutProof;
+use store::HeaderStore;
+
+/// Block of the stored chain containing the transactions
+pub enum Block {
+    Height(usize),
+    /// Hash in the byte order of `BlockHeader::hash`
+    Hash([u8; 32]),
+}
+
+/// Transactions proven in a block of the stored chain
+#[derive(Clone, Debug, PartialEq)]
+pub struct Inclusion {
+    pub height: usize,
+    pub block_hash: [u8; 32],
+    /// 1 for a transaction in the tip
+    pub confirmations: usize,
+    pub txids: Vec<[u8; 32]>,
+}
+
+fn inclusion(store: &dyn HeaderStore, height: usize, txids: Vec<[u8; 32]>) -> Result<Inclusion, &'static str> {
+    let block_hash = store.hash_at(height).ok_or("Block not in the stored chain")?;
+    Ok(Inclusion {
+        height,
+        block_hash,
+        confirmations: store.len().saturating_sub(height),
+        txids,
+    })
+}
+
+/// Check `txid`, at position `index` in the block, with the merkle `branch` from the leaf up,
+/// is in `block`
+pub fn verify_branch(
+    store: &dyn HeaderStore,
+    txid: &[u8; 32],
+    branch: &[[u8; 32]],
+    index: u32,
+    block: Block,
+) -> Result<Inclusion, &'static str> {
+    let (height, block_header) = match block {
+        Block::Height(height) => store.header_at(height).map(|header| (height, header)),
+        Block::Hash(hash) => store.header_by_hash(&hash),
+    }.ok_or("Block not in the stored chain")?;
+    if branch.len() > merkle::MAX_DEPTH {
+        return Err("Branch longer than the tree of a block");
+    }
+    if index >> branch.len() != 0 {
+        return Err("Index outside the tree of the branch");
+    }
+    match merkle::branch_root(txid, branch, index) == block_header.merkle_root {
+        true => inclusion(store, height, vec![*txid]),
+        false => Err("Branch not matching the merkle root"),
+    }
+}
+
+/// Check the transactions matched by a `gettxoutproof` proof are in the stored chain
+pub fn verify_txout_proof(store: &dyn HeaderStore, proof: &TxOutProof) -> Result<Inclusion, &'static str> {
+    let matches = proof.matches()?;
+    let height = store
+        .height_of(&proof.block_header.hash())
+        .ok_or("Block not in the stored chain")?;
+    let txids = matches.txids.into_iter().map(|(_, txid)| txid).collect();
+    inclusion(store, height, txids)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::header::BlockHeader;
+    use bitcoin::merkle;
+    use bitcoin::merkle::TxOutProof;
+    use bitcoin::merkle::tests::{serialize, tree};
+    use spv;
+    use spv::Block;
+    use store::{HeaderStore, MemoryStore};
+
+    #[test]
+    pub fn test_spv() {
+        let txids: Vec<[u8; 32]> = (0..3u8).map(|i| [i; 32]).collect();
+        let genesis = BlockHeader::genesis();
+        let mut block_header = genesis;
+        block_header.prev_blockhash = genesis.hash();
+        block_header.merkle_root = merkle::root(&txids);
+        let mut next = block_header;
+        next.prev_blockhash = block_header.hash();
+        next.merkle_root = txids[0];
+        let store = MemoryStore::new();
+        store.extend(&[genesis, block_header, next]).unwrap();
+
+        let branch = [txids[0], merkle::parent(&txids[2], &txids[2])];
+        let inclusion = spv::verify_branch(&store, &txids[1], &branch, 1, Block::Height(1)).unwrap();
+        assert_eq!((inclusion.height, inclusion.confirmations), (1, 2));
+        assert_eq!(inclusion.block_hash, block_header.hash());
+        let by_hash = spv::verify_branch(&store, &txids[1], &branch, 1, Block::Hash(block_header.hash()));
+        assert_eq!(by_hash, Ok(inclusion));
+        assert!(spv::verify_branch(&store, &txids[1], &branch, 5, Block::Height(1)).is_err());
+        assert!(spv::verify_branch(&store, &txids[1], &branch, 1, Block::Height(2)).is_err());
+        assert!(spv::verify_branch(&store, &txids[1], &branch, 1, Block::Height(3)).is_err());
+        let long_branch = [txids[0]; 40];
+        assert!(spv::verify_branch(&store, &txids[1], &long_branch, 1, Block::Height(1)).is_err());
+
+        let proof = TxOutProof::parse(&serialize(&block_header, &tree(&txids, 2))).unwrap();
+        let inclusion = spv::verify_txout_proof(&store, &proof).unwrap();
+        assert_eq!((inclusion.height, inclusion.txids), (1, vec![txids[2]]));
+
+        store.truncate(1).unwrap();
+        assert!(spv::verify_txout_proof(&store, &proof).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use bitcoin::header::BlockHeader;
+use bitcoin::stream;
+use store::{check_range, index_path, read_consistent, HeaderStore, Index};
+
+struct FileInner {
+    file: File,
+    byte_len: usize,
+    index: Index,
+}
+
+/// Store persisting the stream in a file, read ranges are served from the file
+/// while the hashes are kept in memory and persisted in a `.hashes` file beside it.
+/// Ranges are read with their own handle, not waiting for the writer: appends only write
+/// after the published length and a read overlapping a truncation, even starting and ending
+/// inside it, is retried
+pub struct FileStore {
+    inner: Mutex<FileInner>,
+    reader: Mutex<File>,
+    byte_len: AtomicUsize,
+    generation: AtomicUsize,
+}
+
+impl FileStore {
+    /// Open or create the stream file at `path`, a trailing incomplete header is discarded.
+    /// The hash index is rebuilt if it doesn't match the stream
+    pub fn open(path: &Path) -> io::Result<FileStore> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let count = stream::count(bytes.len());
+        let byte_len = stream::byte_len(count);
+        let mut index = Index::open(&index_path(path))?;
+        index.load_bits(count, |height| {
+            let mut header_bytes = [0u8; 80];
+            header_bytes.copy_from_slice(&bytes[stream::offset(height)..][..80]);
+            header_bytes
+        });
+        if !index.matches(count, &bytes[..byte_len]) {
+            let block_headers = match stream::decode(&bytes[..byte_len]) {
+                Ok(block_headers) => block_headers,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+            index.clear()?;
+            for block_header in block_headers.iter() {
+                index.check(block_header)?;
+                index.push(block_header);
+            }
+            index.save()?;
+        }
+        if byte_len != bytes.len() {
+            file.set_len(byte_len as u64)?;
+        }
+        Ok(FileStore {
+            reader: Mutex::new(File::open(path)?),
+            inner: Mutex::new(FileInner {
+                file,
+                byte_len,
+                index,
+            }),
+            byte_len: AtomicUsize::new(byte_len),
+            generation: AtomicUsize::new(0),
+        })
+    }
+}
+
+fn read(file: &mut File, start: usize, end: usize) -> io::Result<Vec<u8>> {
+    let mut result = vec![0u8; end - start];
+    file.seek(SeekFrom::Start(start as u64))?;
+    file.read_exact(&mut result)?;
+    Ok(result)
+}
+
+/// Remove the headers from `height` on, publishing the new length before shrinking the file
+fn truncate(inner: &mut FileInner, byte_len: &AtomicUsize, height: usize) -> io::Result<()> {
+    inner.index.truncate(height)?;
+    inner.byte_len = stream::byte_len(inner.index.len());
+    byte_len.store(inner.byte_len, Ordering::SeqCst);
+    inner.file.set_len(inner.byte_len as u64)
+}
+
+impl HeaderStore for FileStore {
+    fn len(&self) -> usize {
+        stream::count(self.byte_len())
+    }
+
+    fn byte_len(&self) -> usize {
+        self.byte_len.load(Ordering::SeqCst)
+    }
+
+    fn append(&self, block_header: &BlockHeader) -> io::Result<()> {
+        self.extend(&[*block_header])
+    }
+
+    fn extend(&self, block_headers: &[BlockHeader]) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut bytes = Vec::new();
+        let mut result = Ok(());
+        for block_header in block_headers {
+            if let Err(e) = inner.index.check(block_header) {
+                result = Err(e);
+                break;
+            }
+            let height = inner.index.len();
+            stream::append(&mut bytes, height, block_header);
+            inner.index.push(block_header);
+        }
+        let byte_len = inner.byte_len;
+        inner.file.seek(SeekFrom::Start(byte_len as u64))?;
+        inner.file.write_all(&bytes)?;
+        inner.file.flush()?;
+        inner.byte_len += bytes.len();
+        self.byte_len.store(inner.byte_len, Ordering::SeqCst);
+        inner.index.save()?;
+        result
+    }
+
+    fn truncate(&self, height: usize) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let result = truncate(&mut inner, &self.byte_len, height);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        result
+    }
+
+    fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn read_range(&self, start: usize, end: usize) -> io::Result<Vec<u8>> {
+        let mut reader = self.reader.lock().unwrap();
+        read_consistent(self, || {
+            check_range(start, end, self.byte_len())?;
+            read(&mut reader, start, end)
+        })
+    }
+
+    fn header_at(&self, height: usize) -> Option<BlockHeader> {
+        let mut inner = self.inner.lock().unwrap();
+        if height >= inner.index.len() {
+            return None;
+        }
+        let start = stream::offset(height);
+        let bytes = read(&mut inner.file, start, start + stream::header_len(height)).ok()?;
+        Some(inner.index.header(height, &bytes))
+    }
+
+    fn hash_at(&self, height: usize) -> Option<[u8; 32]> {
+        self.inner.lock().unwrap().index.hashes.get(height).cloned()
+    }
+
+    fn height_of(&self, hash: &[u8; 32]) -> Option<usize> {
+        self.inner.lock().unwrap().index.heights.get(hash).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::stream;
+    use std::env;
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::process;
+    use store::file::FileStore;
+    use store::tests::check_store;
+    use store::{index_path, HeaderStore};
+
+    #[test]
+    pub fn test_file_store() {
+        let path = env::temp_dir().join(format!("compressedheaders-store-{}", process::id()));
+        let index_path = index_path(&path);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&index_path);
+        check_store(&FileStore::open(&path).unwrap());
+        let block_headers = stream::decode(include_bytes!("../../examples/2016/0")).unwrap();
+
+        // reopening restores the index and discards a partially written header
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&[0u8; 30]).unwrap();
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 1501);
+        assert_eq!(store.byte_len(), stream::byte_len(1501));
+        assert_eq!(fs::metadata(&path).unwrap().len() as usize, stream::byte_len(1501));
+        assert!(store.header_at(1500).is_some());
+        assert_eq!(fs::metadata(&index_path).unwrap().len(), 1501 * 32);
+
+        // a missing or stale hash index is rebuilt
+        let hash = store.hash_at(1500).unwrap();
+        let mut hashes = fs::read(&index_path).unwrap();
+        for byte in hashes[900 * 32..1000 * 32].iter_mut() {
+            *byte = 0;
+        }
+        fs::write(&index_path, &hashes).unwrap();
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.hash_at(950), Some(block_headers[950].hash()));
+        assert_eq!(store.height_of(&block_headers[950].hash()), Some(950));
+        fs::write(&index_path, [0u8; 64]).unwrap();
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.height_of(&hash), Some(1500));
+        assert_eq!(fs::metadata(&index_path).unwrap().len(), 1501 * 32);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&index_path).unwrap();
+    }
+}
@@ -0,0 +1,499 @@
+use memmap::Mmap;
+use std::cmp::Ordering;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use std::sync::{atomic, Arc, Mutex, RwLock};
+use bitcoin::hash;
+use bitcoin::header::BlockHeader;
+use bitcoin::stream;
+use store::{check_range, HeaderStore};
+use store::slice::Slice;
+
+/// Length of the hashes file of a chunk: the hashes by height, the positions of the
+/// headers sorted by hash, 2 bytes each, and a checksum of both
+const HASHES_LEN: usize = stream::CHUNK_SIZE * 34 + 32;
+
+/// A completed chunk, the stream and the hashes of its headers, both mapped
+#[derive(Clone)]
+struct Chunk {
+    bytes: Arc<Mmap>,
+    hashes: Arc<Mmap>,
+}
+
+impl Chunk {
+    fn hash(&self, position: usize) -> [u8; 32] {
+        hash_in(&self.hashes, position)
+    }
+
+    /// Position in the chunk of the header with `hash`, binary searched in the sorted positions
+    fn position_of(&self, hash: &[u8; 32]) -> Option<usize> {
+        let sorted = &self.hashes[stream::CHUNK_SIZE * 32..stream::CHUNK_SIZE * 34];
+        let (mut low, mut high) = (0, stream::CHUNK_SIZE);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let position = u16::from_le_bytes([sorted[mid * 2], sorted[mid * 2 + 1]]) as usize;
+            match self.hashes[position * 32..position * 32 + 32].cmp(&hash[..]) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Some(position),
+            }
+        }
+        None
+    }
+}
+
+fn hash_in(hashes: &[u8], position: usize) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&hashes[position * 32..position * 32 + 32]);
+    hash
+}
+
+/// Rebuild the header at `position` in the chunk `bytes`, following the header with `prev_hash`
+fn rebuild(bytes: &[u8], position: usize, prev_hash: [u8; 32]) -> BlockHeader {
+    let mut header_bytes = [0u8; 80];
+    header_bytes.copy_from_slice(&bytes[..80]);
+    let full = BlockHeader::from_bytes(header_bytes);
+    if position == 0 {
+        return full;
+    }
+    let mut compressed_bytes = [0u8; 44];
+    compressed_bytes.copy_from_slice(&bytes[stream::offset(position)..][..44]);
+    BlockHeader::from_compressed_bytes(compressed_bytes, prev_hash, full.bits)
+}
+
+/// Content of the hashes file of a chunk with the headers `hashes`
+fn hashes_bytes(hashes: &[[u8; 32]]) -> Vec<u8> {
+    let mut positions: Vec<u16> = (0..hashes.len() as u16).collect();
+    positions.sort_by_key(|&position| hashes[position as usize]);
+    let mut bytes = Vec::with_capacity(HASHES_LEN);
+    for hash in hashes {
+        bytes.extend_from_slice(hash);
+    }
+    for position in positions {
+        bytes.extend_from_slice(&position.to_le_bytes());
+    }
+    let checksum = hash::sha256d(&bytes);
+    bytes.extend_from_slice(&checksum);
+    bytes
+}
+
+/// Whether `hashes` is the hashes file of the chunk `bytes`: the checksum, the full header
+/// and some headers rebuilt from their previous hash are checked, without decoding the chunk
+fn check_hashes(bytes: &[u8], hashes: &[u8]) -> bool {
+    if hashes.len() != HASHES_LEN || hash::sha256d(&hashes[..HASHES_LEN - 32])[..] != hashes[HASHES_LEN - 32..] {
+        return false;
+    }
+    [0, 1, stream::CHUNK_SIZE / 2, stream::CHUNK_SIZE - 1].iter().all(|&position| {
+        let prev_hash = match position {
+            0 => [0; 32],
+            _ => hash_in(hashes, position - 1),
+        };
+        rebuild(bytes, position, prev_hash).hash() == hash_in(hashes, position)
+    })
+}
+
+/// Headers of the store as seen by readers: the completed chunks are mapped files,
+/// the open chunk, never bigger than a chunk, is kept in memory
+#[derive(Clone)]
+struct View {
+    chunks: Vec<Chunk>,
+    tail: Vec<u8>,
+    /// Hashes of the headers of the open chunk
+    tail_hashes: Vec<[u8; 32]>,
+    count: usize,
+}
+
+impl View {
+    fn chunk(&self, index: usize) -> &[u8] {
+        match self.chunks.get(index) {
+            Some(chunk) => &chunk.bytes,
+            None => &self.tail,
+        }
+    }
+
+    fn hash_at(&self, height: usize) -> Option<[u8; 32]> {
+        let position = height % stream::CHUNK_SIZE;
+        match self.chunks.get(height / stream::CHUNK_SIZE) {
+            Some(chunk) => Some(chunk.hash(position)),
+            None if height < self.count => Some(self.tail_hashes[position]),
+            None => None,
+        }
+    }
+
+    fn header_at(&self, height: usize) -> Option<BlockHeader> {
+        if height >= self.count {
+            return None;
+        }
+        let prev_hash = match height % stream::CHUNK_SIZE {
+            0 => [0; 32],
+            _ => self.hash_at(height - 1)?,
+        };
+        Some(rebuild(self.chunk(height / stream::CHUNK_SIZE), height % stream::CHUNK_SIZE, prev_hash))
+    }
+
+    fn height_of(&self, hash: &[u8; 32]) -> Option<usize> {
+        let open = self.chunks.len() * stream::CHUNK_SIZE;
+        if let Some(position) = self.tail_hashes.iter().position(|h| h == hash) {
+            return Some(open + position);
+        }
+        self.chunks
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(index, chunk)| chunk.position_of(hash).map(|p| index * stream::CHUNK_SIZE + p))
+            .next()
+    }
+
+    /// Check the header is the next one in the chain
+    fn check(&self, block_header: &BlockHeader) -> io::Result<()> {
+        let height = self.count;
+        let prev_hash = height.checked_sub(1).and_then(|h| self.hash_at(h)).unwrap_or([0; 32]);
+        if block_header.prev_blockhash != prev_hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("header at height {} not connecting", height),
+            ));
+        }
+        if !stream::is_full(height) && block_header.bits[..] != self.tail[72..76] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("header at height {} changes bits inside a chunk", height),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Store persisting the stream in a directory with a file per chunk. Completed chunks
+/// are memory mapped and never modified, a reorg crossing a chunk boundary replaces
+/// the file instead, so readers holding the previous mapping are not affected.
+/// The hashes of a completed chunk are in a mapped file beside it, with its headers
+/// sorted by hash for the lookups by hash, so besides the open chunk nothing is in memory,
+/// nothing is decoded at startup and readers never wait for the writer.
+pub struct MmapStore {
+    dir: PathBuf,
+    /// The file of the open chunk
+    writer: Mutex<File>,
+    view: RwLock<Arc<View>>,
+    generation: AtomicUsize,
+}
+
+/// Map the completed chunk or hashes in `file`. Responses hold the mapping while sending it,
+/// so a chunk replaced by a reorg is unmapped once the last of them is done
+fn map_file(file: &File) -> io::Result<Arc<Mmap>> {
+    Ok(Arc::new(unsafe { Mmap::map(file)? }))
+}
+
+fn chunk_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("{:06}", index))
+}
+
+fn hashes_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("{:06}.hashes", index))
+}
+
+fn open_chunk(dir: &Path, index: usize) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(chunk_path(dir, index))
+}
+
+/// Write and map the hashes file of the chunk `index`, replacing it atomically
+fn write_hashes(dir: &Path, index: usize, hashes: &[[u8; 32]]) -> io::Result<Arc<Mmap>> {
+    let tmp_path = dir.join("tmp.hashes");
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(&hashes_bytes(hashes))?;
+    tmp.sync_data()?;
+    fs::rename(&tmp_path, hashes_path(dir, index))?;
+    map_file(&File::open(hashes_path(dir, index))?)
+}
+
+/// The mapped hashes of the completed chunk `index` with `bytes`, rebuilt if missing or
+/// not matching the chunk
+fn open_hashes(dir: &Path, index: usize, bytes: &[u8]) -> io::Result<Arc<Mmap>> {
+    if let Ok(file) = File::open(hashes_path(dir, index)) {
+        if file.metadata()?.len() as usize == HASHES_LEN {
+            let hashes = map_file(&file)?;
+            if check_hashes(bytes, &hashes) {
+                return Ok(hashes);
+            }
+        }
+    }
+    let block_headers = stream::decode(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let hashes: Vec<[u8; 32]> = block_headers.iter().map(BlockHeader::hash).collect();
+    write_hashes(dir, index, &hashes)
+}
+
+/// Remove a file if it exists
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn not_connecting(height: usize) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("header at height {} not connecting", height))
+}
+
+impl MmapStore {
+    /// Open or create the store in the directory `dir`, a trailing incomplete header is discarded
+    pub fn open(dir: &Path) -> io::Result<MmapStore> {
+        fs::create_dir_all(dir)?;
+        let chunk_len = stream::byte_len(stream::CHUNK_SIZE);
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut prev_hash = [0u8; 32];
+        let file = loop {
+            let index = chunks.len();
+            let file = open_chunk(dir, index)?;
+            if file.metadata()?.len() as usize != chunk_len {
+                break file;
+            }
+            let bytes = map_file(&file)?;
+            if rebuild(&bytes, 0, prev_hash).prev_blockhash != prev_hash {
+                return Err(not_connecting(index * stream::CHUNK_SIZE));
+            }
+            let chunk = Chunk {
+                hashes: open_hashes(dir, index, &bytes)?,
+                bytes,
+            };
+            prev_hash = chunk.hash(stream::CHUNK_SIZE - 1);
+            chunks.push(chunk);
+        };
+        let mut tail = Vec::new();
+        (&file).read_to_end(&mut tail)?;
+        let len = stream::byte_len(stream::count(tail.len()));
+        if len != tail.len() {
+            tail.truncate(len);
+            file.set_len(len as u64)?;
+        }
+        // the open chunk is decoded, it's never bigger than a chunk
+        let open = chunks.len() * stream::CHUNK_SIZE;
+        let block_headers = stream::decode(&tail).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if block_headers.first().is_some_and(|first| first.prev_blockhash != prev_hash) {
+            return Err(not_connecting(open));
+        }
+        let view = View {
+            count: open + block_headers.len(),
+            chunks,
+            tail,
+            tail_hashes: block_headers.iter().map(BlockHeader::hash).collect(),
+        };
+        Ok(MmapStore {
+            dir: dir.to_path_buf(),
+            writer: Mutex::new(file),
+            view: RwLock::new(Arc::new(view)),
+            generation: AtomicUsize::new(0),
+        })
+    }
+
+    fn view(&self) -> Arc<View> {
+        self.view.read().unwrap().clone()
+    }
+
+    /// Remove the headers from `height` on, `file` is the one of the open chunk
+    fn remove(&self, file: &mut File, height: usize) -> io::Result<()> {
+        let old = self.view();
+        if height >= old.count {
+            return Ok(());
+        }
+        let index = height / stream::CHUNK_SIZE;
+        let tail_len = stream::byte_len(height % stream::CHUNK_SIZE);
+        let view = View {
+            chunks: old.chunks[..index.min(old.chunks.len())].to_vec(),
+            tail: old.chunk(index)[..tail_len].to_vec(),
+            tail_hashes: (index * stream::CHUNK_SIZE..height).filter_map(|h| old.hash_at(h)).collect(),
+            count: height,
+        };
+        if index < old.chunks.len() {
+            // mapped files are replaced, never truncated
+            for i in index + 1..old.count.div_ceil(stream::CHUNK_SIZE) {
+                fs::remove_file(chunk_path(&self.dir, i))?;
+                remove_file(&hashes_path(&self.dir, i))?;
+            }
+            remove_file(&hashes_path(&self.dir, index))?;
+            let tmp_path = self.dir.join("tmp");
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&view.tail)?;
+            tmp.sync_data()?;
+            fs::rename(&tmp_path, chunk_path(&self.dir, index))?;
+            *file = open_chunk(&self.dir, index)?;
+        } else {
+            file.set_len(tail_len as u64)?;
+        }
+        *self.view.write().unwrap() = Arc::new(view);
+        Ok(())
+    }
+}
+
+impl HeaderStore for MmapStore {
+    fn len(&self) -> usize {
+        self.view().count
+    }
+
+    fn byte_len(&self) -> usize {
+        stream::byte_len(self.view().count)
+    }
+
+    fn append(&self, block_header: &BlockHeader) -> io::Result<()> {
+        self.extend(&[*block_header])
+    }
+
+    fn extend(&self, block_headers: &[BlockHeader]) -> io::Result<()> {
+        let mut file = self.writer.lock().unwrap();
+        let mut view = (*self.view()).clone();
+        let mut written = view.tail.len();
+        let mut result = Ok(());
+        for block_header in block_headers {
+            if let Err(e) = view.check(block_header) {
+                result = Err(e);
+                break;
+            }
+            let height = view.count;
+            stream::append(&mut view.tail, height, block_header);
+            view.tail_hashes.push(block_header.hash());
+            view.count += 1;
+
+            if view.count.is_multiple_of(stream::CHUNK_SIZE) {
+                file.write_all(&view.tail[written..])?;
+                file.sync_data()?;
+                let index = view.chunks.len();
+                view.chunks.push(Chunk {
+                    bytes: map_file(&file)?,
+                    hashes: write_hashes(&self.dir, index, &view.tail_hashes)?,
+                });
+                view.tail.clear();
+                view.tail_hashes.clear();
+                written = 0;
+                *file = open_chunk(&self.dir, index + 1)?;
+            }
+        }
+        file.write_all(&view.tail[written..])?;
+        file.flush()?;
+        *self.view.write().unwrap() = Arc::new(view);
+        result
+    }
+
+    fn truncate(&self, height: usize) -> io::Result<()> {
+        let mut file = self.writer.lock().unwrap();
+        self.generation.fetch_add(1, atomic::Ordering::SeqCst);
+        let result = self.remove(&mut file, height);
+        self.generation.fetch_add(1, atomic::Ordering::SeqCst);
+        result
+    }
+
+    fn generation(&self) -> usize {
+        self.generation.load(atomic::Ordering::SeqCst)
+    }
+
+    fn read_range(&self, start: usize, end: usize) -> io::Result<Vec<u8>> {
+        Ok(self.read_slices(start, end)?.concat())
+    }
+
+    fn read_slices(&self, start: usize, end: usize) -> io::Result<Vec<Slice>> {
+        let view = self.view();
+        check_range(start, end, stream::byte_len(view.count))?;
+        let chunk_len = stream::byte_len(stream::CHUNK_SIZE);
+        let mut result = Vec::new();
+        let mut pos = start;
+        while pos < end {
+            let index = pos / chunk_len;
+            let offset = pos % chunk_len;
+            let to = view.chunk(index).len().min(offset + end - pos);
+            result.push(match view.chunks.get(index) {
+                Some(chunk) => Slice::Mapped(chunk.bytes.clone(), offset, to),
+                None => Slice::from(&view.tail[offset..to]),
+            });
+            pos += to - offset;
+        }
+        Ok(result)
+    }
+
+    fn header_at(&self, height: usize) -> Option<BlockHeader> {
+        self.view().header_at(height)
+    }
+
+    fn hash_at(&self, height: usize) -> Option<[u8; 32]> {
+        self.view().hash_at(height)
+    }
+
+    fn height_of(&self, hash: &[u8; 32]) -> Option<usize> {
+        self.view().height_of(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::header::BlockHeader;
+    use bitcoin::stream;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use store::mmap::{MmapStore, HASHES_LEN};
+    use store::tests::check_store;
+    use store::HeaderStore;
+
+    #[test]
+    pub fn test_mmap_store() {
+        let dir = env::temp_dir().join(format!("compressedheaders-mmap-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        check_store(&MmapStore::open(&dir).unwrap());
+        let store = MmapStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 1501);
+
+        // cross chunk boundaries, then reorg back into a mapped chunk
+        let mut block_headers = stream::decode(&store.read_range(0, store.byte_len()).unwrap()).unwrap();
+        while block_headers.len() < 5000 {
+            let mut block_header = block_headers[block_headers.len() - 1];
+            block_header.prev_blockhash = block_header.hash();
+            block_headers.push(block_header);
+        }
+        store.extend(&block_headers[1501..]).unwrap();
+        let bytes = stream::encode(&block_headers);
+        let reader = MmapStore::open(&dir).unwrap();
+        assert_eq!(reader.read_range(0, bytes.len()).unwrap(), bytes);
+        // mapped chunks are served without copying, a slice for every chunk
+        let slices = reader.read_slices(100, bytes.len()).unwrap();
+        assert_eq!(slices.len(), 3);
+        assert_eq!(slices.concat(), &bytes[100..]);
+        assert_eq!(store.height_of(&block_headers[2500].hash()), Some(2500));
+
+        store.truncate(3000).unwrap();
+        store.truncate(2016).unwrap();
+        assert_eq!(reader.read_range(0, bytes.len()).unwrap(), bytes);
+        let mut fork: BlockHeader = block_headers[2016];
+        fork.nonce = [0; 4];
+        store.append(&fork).unwrap();
+        assert_eq!(store.hash_at(2016), Some(fork.hash()));
+
+        let store = MmapStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 2017);
+        assert_eq!(store.header_at(2016).unwrap().hash(), fork.hash());
+        assert_eq!(store.read_range(0, stream::byte_len(2016)).unwrap(), &bytes[..stream::byte_len(2016)]);
+
+        // a stale or missing hashes file is rebuilt from its chunk
+        let hashes_path = dir.join("000000.hashes");
+        let mut hashes = fs::read(&hashes_path).unwrap();
+        hashes[500 * 32] ^= 1;
+        fs::write(&hashes_path, &hashes).unwrap();
+        let store = MmapStore::open(&dir).unwrap();
+        assert_eq!(store.height_of(&block_headers[500].hash()), Some(500));
+        assert_eq!(store.hash_at(500), Some(block_headers[500].hash()));
+        fs::remove_file(&hashes_path).unwrap();
+        let store = MmapStore::open(&dir).unwrap();
+        for height in &[0, 1, 1000, 2015] {
+            assert_eq!(store.height_of(&block_headers[*height].hash()), Some(*height));
+        }
+        assert!(store.height_of(&block_headers[3000].hash()).is_none());
+        assert_eq!(fs::metadata(&hashes_path).unwrap().len() as usize, HASHES_LEN);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,487 @@
+pub mod file;
+pub mod mmap;
+pub mod slice;
+pub mod snapshot;
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use bitcoin::header;
+use bitcoin::header::BlockHeader;
+use bitcoin::stream;
+use store::slice::Slice;
+use store::snapshot::Snapshot;
+
+/// Storage of the compressed headers stream, shared between the sync loop and the server
+pub trait HeaderStore: Send + Sync {
+    /// Number of headers stored, which is also the height of the next header
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Length in bytes of the compressed stream
+    fn byte_len(&self) -> usize;
+
+    /// Append the header following the last stored one
+    fn append(&self, block_header: &BlockHeader) -> io::Result<()>;
+
+    fn extend(&self, block_headers: &[BlockHeader]) -> io::Result<()> {
+        for block_header in block_headers {
+            self.append(block_header)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the headers from `height` on
+    fn truncate(&self, height: usize) -> io::Result<()>;
+
+    /// Increased before and after every truncation, so it's odd while one is in progress,
+    /// see `read_consistent`
+    fn generation(&self) -> usize;
+
+    /// The bytes `start..end` of the compressed stream
+    fn read_range(&self, start: usize, end: usize) -> io::Result<Vec<u8>>;
+
+    /// The bytes `start..end` as a list of slices, stores able to share their
+    /// buffers avoid copying them
+    fn read_slices(&self, start: usize, end: usize) -> io::Result<Vec<Slice>> {
+        Ok(vec![Slice::from(self.read_range(start, end)?)])
+    }
+
+    fn header_at(&self, height: usize) -> Option<BlockHeader>;
+
+    fn hash_at(&self, height: usize) -> Option<[u8; 32]>;
+
+    fn height_of(&self, hash: &[u8; 32]) -> Option<usize>;
+
+    fn header_by_hash(&self, hash: &[u8; 32]) -> Option<(usize, BlockHeader)> {
+        self.height_of(hash)
+            .and_then(|height| self.header_at(height).map(|header| (height, header)))
+    }
+
+    /// Median time past of the header at `height`, which never decreases with the height
+    fn median_time_past(&self, height: usize) -> Option<u32> {
+        let start = (height + 1).saturating_sub(header::MEDIAN_TIME_SPAN);
+        let block_headers: Option<Vec<BlockHeader>> =
+            (start..=height).map(|h| self.header_at(h)).collect();
+        block_headers.map(|block_headers| header::median_time(&block_headers))
+    }
+
+    /// First height with median time past at or after `time`, binary searched since the
+    /// median time past is monotonic while the block times are not
+    fn height_at_time(&self, time: u32) -> Option<usize> {
+        let mut low = 0;
+        let mut high = self.len();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.median_time_past(mid)? < time {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        match low < self.len() {
+            true => Some(low),
+            false => None,
+        }
+    }
+}
+
+/// Run `read`, made of several reads of `store`, until no truncation overlaps it, so the
+/// data read is from a single chain even if a reorg happens meanwhile
+pub fn read_consistent<T, F: FnMut() -> T>(store: &dyn HeaderStore, mut read: F) -> T {
+    loop {
+        let generation = store.generation();
+        if generation.is_multiple_of(2) {
+            let result = read();
+            if store.generation() == generation {
+                return result;
+            }
+        }
+        thread::yield_now();
+    }
+}
+
+/// Distance of the persisted hashes checked at startup, see `Index::matches`
+const SAMPLE_STEP: usize = 64;
+
+/// Hashes of the stored headers, by height and by hash, with the bits of every chunk
+/// needed to rebuild the compressed headers. The hashes can be persisted in a file,
+/// 32 bytes for every height, so they are not recomputed at startup
+struct Index {
+    hashes: Vec<[u8; 32]>,
+    heights: HashMap<[u8; 32], usize>,
+    bits: Vec<[u8; 4]>,
+    file: Option<File>,
+    unsaved: Vec<u8>,
+}
+
+impl Index {
+    fn new() -> Index {
+        Index {
+            hashes: Vec::new(),
+            heights: HashMap::new(),
+            bits: Vec::new(),
+            file: None,
+            unsaved: Vec::new(),
+        }
+    }
+
+    /// Index persisted in the file at `path`, the bits must be loaded with `load_bits`
+    fn open(path: &Path) -> io::Result<Index> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut index = Index::new();
+        for (height, hash_bytes) in bytes.chunks_exact(32).enumerate() {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(hash_bytes);
+            index.heights.insert(hash, height);
+            index.hashes.push(hash);
+        }
+        file.set_len(index.len() as u64 * 32)?;
+        index.file = Some(file);
+        Ok(index)
+    }
+
+    /// Take the bits of every chunk from the full header at its start, `count` headers are
+    /// in the stream and `full_header` gives the full header at a height
+    fn load_bits<F: Fn(usize) -> [u8; 80]>(&mut self, count: usize, full_header: F) {
+        self.bits = (0..count.div_ceil(stream::CHUNK_SIZE))
+            .map(|i| BlockHeader::from_bytes(full_header(i * stream::CHUNK_SIZE)).bits)
+            .collect();
+    }
+
+    /// Whether the persisted hashes match the stream `bytes` of `count` headers, the bits must
+    /// be loaded. Every chunk must link to the previous one and a header every `SAMPLE_STEP`,
+    /// with the last one, is rebuilt from the previous hash, checking two hashes each time,
+    /// so a stale range of hashes is found without decoding the whole stream
+    fn matches(&self, count: usize, bytes: &[u8]) -> bool {
+        if self.len() != count {
+            return false;
+        }
+        let rebuilt = |height: usize| self.header(height, &bytes[stream::offset(height)..]);
+        let linked = (stream::CHUNK_SIZE..count)
+            .step_by(stream::CHUNK_SIZE)
+            .all(|height| rebuilt(height).prev_blockhash == self.hashes[height - 1]);
+        let sampled = (0..count)
+            .step_by(SAMPLE_STEP)
+            .chain(count.checked_sub(1))
+            .all(|height| rebuilt(height).hash() == self.hashes[height]);
+        linked && sampled
+    }
+
+    /// Clear the index, to rebuild it pushing again every header
+    fn clear(&mut self) -> io::Result<()> {
+        self.truncate(0)?;
+        self.bits.clear();
+        Ok(())
+    }
+
+    /// Write the hashes pushed since the last save
+    fn save(&mut self) -> io::Result<()> {
+        if let Some(ref mut file) = self.file {
+            file.seek(SeekFrom::Start(self.hashes.len() as u64 * 32 - self.unsaved.len() as u64))?;
+            file.write_all(&self.unsaved)?;
+            file.flush()?;
+        }
+        self.unsaved.clear();
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Check the header is the next one in the chain
+    fn check(&self, block_header: &BlockHeader) -> io::Result<()> {
+        let prev_hash = self.hashes.last().cloned().unwrap_or([0; 32]);
+        if block_header.prev_blockhash != prev_hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("header at height {} not connecting", self.len()),
+            ));
+        }
+        if !stream::is_full(self.len()) && block_header.bits != self.bits[self.bits.len() - 1] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("header at height {} changes bits inside a chunk", self.len()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, block_header: &BlockHeader) {
+        let height = self.len();
+        if stream::is_full(height) {
+            self.bits.push(block_header.bits);
+        }
+        let hash = block_header.hash();
+        self.heights.insert(hash, height);
+        self.hashes.push(hash);
+        if self.file.is_some() {
+            self.unsaved.extend_from_slice(&hash);
+        }
+    }
+
+    fn truncate(&mut self, height: usize) -> io::Result<()> {
+        self.save()?;
+        for hash in self.hashes.drain(height.min(self.hashes.len())..) {
+            self.heights.remove(&hash);
+        }
+        self.bits.truncate(height.div_ceil(stream::CHUNK_SIZE));
+        if let Some(ref file) = self.file {
+            file.set_len(self.hashes.len() as u64 * 32)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild the header at `height` from its bytes in the stream
+    fn header(&self, height: usize, bytes: &[u8]) -> BlockHeader {
+        match height % stream::CHUNK_SIZE {
+            0 => {
+                let mut header_bytes: [u8; 80] = [0; 80];
+                header_bytes.clone_from_slice(&bytes[..80]);
+                BlockHeader::from_bytes(header_bytes)
+            }
+            _ => {
+                let mut compressed_bytes: [u8; 44] = [0; 44];
+                compressed_bytes.clone_from_slice(&bytes[..44]);
+                BlockHeader::from_compressed_bytes(
+                    compressed_bytes,
+                    self.hashes[height - 1],
+                    self.bits[height / stream::CHUNK_SIZE],
+                )
+            }
+        }
+    }
+}
+
+/// The file persisting the hash index of the stream at `path`
+fn index_path(path: &Path) -> PathBuf {
+    let mut index_path = path.as_os_str().to_owned();
+    index_path.push(".hashes");
+    PathBuf::from(index_path)
+}
+
+fn check_range(start: usize, end: usize, byte_len: usize) -> io::Result<()> {
+    if start > end || end > byte_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("range {}-{} outside stream of {} bytes", start, end, byte_len),
+        ));
+    }
+    Ok(())
+}
+
+/// Store keeping the whole stream in memory as a `Snapshot`. Readers clone the
+/// current snapshot, the lock is held just for that and to replace it after a write,
+/// so readers never wait for the writer building a new snapshot and vice versa.
+pub struct MemoryStore {
+    index: Mutex<Index>,
+    snapshot: RwLock<Snapshot>,
+    generation: AtomicUsize,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            index: Mutex::new(Index::new()),
+            snapshot: RwLock::new(Snapshot::new()),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    /// The current immutable view of the stream
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.read().unwrap().clone()
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new()
+    }
+}
+
+impl HeaderStore for MemoryStore {
+    fn len(&self) -> usize {
+        self.snapshot().len()
+    }
+
+    fn byte_len(&self) -> usize {
+        self.snapshot().byte_len()
+    }
+
+    fn append(&self, block_header: &BlockHeader) -> io::Result<()> {
+        self.extend(&[*block_header])
+    }
+
+    fn extend(&self, block_headers: &[BlockHeader]) -> io::Result<()> {
+        let mut index = self.index.lock().unwrap();
+        let mut result = Ok(());
+        let mut appended = 0;
+        for block_header in block_headers {
+            if let Err(e) = index.check(block_header) {
+                result = Err(e);
+                break;
+            }
+            index.push(block_header);
+            appended += 1;
+        }
+        index.save()?;
+        let snapshot = self.snapshot().append(&block_headers[..appended]);
+        *self.snapshot.write().unwrap() = snapshot;
+        result
+    }
+
+    fn truncate(&self, height: usize) -> io::Result<()> {
+        let mut index = self.index.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let result = index.truncate(height);
+        let snapshot = self.snapshot().truncate(index.len());
+        *self.snapshot.write().unwrap() = snapshot;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        result
+    }
+
+    fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn read_range(&self, start: usize, end: usize) -> io::Result<Vec<u8>> {
+        self.snapshot().read(start, end)
+    }
+
+    fn read_slices(&self, start: usize, end: usize) -> io::Result<Vec<Slice>> {
+        Ok(self.snapshot().slices(start, end)?.into_iter().map(Slice::from).collect())
+    }
+
+    fn header_at(&self, height: usize) -> Option<BlockHeader> {
+        let index = self.index.lock().unwrap();
+        if height >= index.len() {
+            return None;
+        }
+        let start = stream::offset(height);
+        let bytes = self
+            .snapshot()
+            .read(start, start + stream::header_len(height))
+            .ok()?;
+        Some(index.header(height, &bytes))
+    }
+
+    fn hash_at(&self, height: usize) -> Option<[u8; 32]> {
+        self.index.lock().unwrap().hashes.get(height).cloned()
+    }
+
+    fn height_of(&self, hash: &[u8; 32]) -> Option<usize> {
+        self.index.lock().unwrap().heights.get(hash).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::header::BlockHeader;
+    use bitcoin::stream;
+    use store;
+    use store::{HeaderStore, MemoryStore};
+
+    /// Exercise a store, which must be empty, with the first 2016 mainnet headers
+    pub fn check_store(store: &dyn HeaderStore) {
+        let test_data = include_bytes!("../../examples/2016/0").to_vec();
+        let block_headers = stream::decode(&test_data).unwrap();
+
+        store.extend(&block_headers[..1000]).unwrap();
+        assert!(store.append(&block_headers[1001]).is_err());
+        for block_header in &block_headers[1000..] {
+            store.append(block_header).unwrap();
+        }
+        assert_eq!(store.len(), 2016);
+        assert_eq!(store.byte_len(), test_data.len());
+        assert_eq!(store.read_range(0, test_data.len()).unwrap(), test_data);
+        assert_eq!(store.read_range(100, 200).unwrap(), &test_data[100..200]);
+        assert!(store.read_range(100, test_data.len() + 1).is_err());
+        let slices = store.read_slices(100, 200).unwrap();
+        assert_eq!(slices.concat(), &test_data[100..200]);
+
+        for height in &[0, 1, 1000, 2015] {
+            let hash = block_headers[*height].hash();
+            assert_eq!(store.hash_at(*height), Some(hash));
+            assert_eq!(store.height_of(&hash), Some(*height));
+            assert_eq!(store.header_at(*height).unwrap().hash(), hash);
+            assert_eq!(store.header_by_hash(&hash).unwrap().0, *height);
+        }
+        assert!(store.header_at(2016).is_none());
+        assert_eq!(store.height_at_time(block_headers[1000].timestamp()), Some(1005));
+        assert_eq!(store.height_at_time(0), Some(0));
+        assert_eq!(store.height_at_time(u32::MAX), None);
+
+        let generation = store.generation();
+        store.truncate(1500).unwrap();
+        assert_eq!(store.generation(), generation + 2);
+        assert_eq!(store.len(), 1500);
+        assert_eq!(store.byte_len(), stream::byte_len(1500));
+        assert!(store.height_of(&block_headers[1500].hash()).is_none());
+        store.append(&block_headers[1500]).unwrap();
+        assert_eq!(store.height_of(&block_headers[1500].hash()), Some(1500));
+    }
+
+    #[test]
+    pub fn test_memory_store() {
+        check_store(&MemoryStore::new());
+    }
+
+    #[test]
+    pub fn test_read_consistent() {
+        let block_headers = stream::decode(include_bytes!("../../examples/2016/0")).unwrap();
+        let store = MemoryStore::new();
+        store.extend(&block_headers[..10]).unwrap();
+        let mut reads = 0;
+        // a reorg during the first read
+        let (len, tip_hash) = store::read_consistent(&store, || {
+            reads += 1;
+            let len = store.len();
+            if reads == 1 {
+                store.truncate(5).unwrap();
+            }
+            (len, store.hash_at(len - 1))
+        });
+        assert_eq!((reads, len, tip_hash), (2, 5, Some(block_headers[4].hash())));
+    }
+
+    #[test]
+    pub fn test_height_at_time() {
+        // times going back and forth, each one after the median time past of the previous block
+        let times = [
+            1000, 1300, 1350, 1400, 1360, 1500, 1420, 1600, 1430, 1700, 1440, 1800, 1450, 1900, 1460,
+        ];
+        let store = MemoryStore::new();
+        let mut prev_hash = [0u8; 32];
+        for time in times.iter() {
+            let mut block_header = BlockHeader::new();
+            block_header.prev_blockhash = prev_hash;
+            block_header.time = u32::to_le_bytes(*time);
+            prev_hash = block_header.hash();
+            store.append(&block_header).unwrap();
+        }
+        assert_eq!(store.median_time_past(6), Some(1360));
+        assert_eq!(store.median_time_past(14), Some(1460));
+        assert_eq!(store.height_at_time(1320), Some(3));
+        assert_eq!(store.height_at_time(1400), Some(7));
+        assert_eq!(store.height_at_time(1401), Some(9));
+        assert_eq!(store.height_at_time(1500), None);
+    }
+}
@@ -0,0 +1,89 @@
+use bytes::Bytes;
+use memmap::Mmap;
+use std::borrow::Borrow;
+use std::sync::Arc;
+
+/// Bytes of the stream shared by a store without copying them, keeping alive the buffer or
+/// the mapping they are in, which is released when the last slice of it is dropped
+#[derive(Clone)]
+pub enum Slice {
+    Bytes(Bytes),
+    /// The range `start..end` of a mapped file
+    Mapped(Arc<Mmap>, usize, usize),
+}
+
+impl Slice {
+    pub fn len(&self) -> usize {
+        self.as_ref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Split the slice at `at`, keeping the bytes before it and returning the ones after
+    pub fn split_off(&mut self, at: usize) -> Slice {
+        assert!(at <= self.len(), "split_off out of bounds");
+        match *self {
+            Slice::Bytes(ref mut bytes) => Slice::Bytes(bytes.split_off(at)),
+            Slice::Mapped(ref mmap, start, ref mut end) => {
+                let rest = Slice::Mapped(mmap.clone(), start + at, *end);
+                *end = start + at;
+                rest
+            }
+        }
+    }
+}
+
+impl AsRef<[u8]> for Slice {
+    fn as_ref(&self) -> &[u8] {
+        match *self {
+            Slice::Bytes(ref bytes) => bytes,
+            Slice::Mapped(ref mmap, start, end) => &mmap[start..end],
+        }
+    }
+}
+
+impl Borrow<[u8]> for Slice {
+    fn borrow(&self) -> &[u8] {
+        self.as_ref()
+    }
+}
+
+impl From<Bytes> for Slice {
+    fn from(bytes: Bytes) -> Slice {
+        Slice::Bytes(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Slice {
+    fn from(bytes: Vec<u8>) -> Slice {
+        Slice::Bytes(Bytes::from(bytes))
+    }
+}
+
+impl<'a> From<&'a [u8]> for Slice {
+    fn from(bytes: &'a [u8]) -> Slice {
+        Slice::Bytes(Bytes::from(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memmap::MmapMut;
+    use std::sync::Arc;
+    use store::slice::Slice;
+
+    #[test]
+    pub fn test_split_off() {
+        let mut mmap = MmapMut::map_anon(10).unwrap();
+        mmap.copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let mmap = Arc::new(mmap.make_read_only().unwrap());
+        for mut slice in [Slice::Mapped(mmap.clone(), 2, 9), Slice::from(&mmap[2..9])] {
+            let rest = slice.split_off(3);
+            assert_eq!(slice.as_ref(), &[2, 3, 4]);
+            assert_eq!(rest.as_ref(), &[5, 6, 7, 8]);
+            assert_eq!([slice, rest].concat(), &mmap[2..9]);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use bytes::Bytes;
+use std::io;
+use std::sync::Arc;
+use bitcoin::header::BlockHeader;
+use bitcoin::stream;
+use store::check_range;
+
+/// Immutable view of the compressed stream. Completed chunks are reference counted
+/// and shared by all the following snapshots, only the open chunk is copied on append.
+#[derive(Clone)]
+pub struct Snapshot {
+    chunks: Arc<Vec<Bytes>>,
+    tail: Bytes,
+    count: usize,
+}
+
+impl Snapshot {
+    pub fn new() -> Snapshot {
+        Snapshot {
+            chunks: Arc::new(Vec::new()),
+            tail: Bytes::new(),
+            count: 0,
+        }
+    }
+
+    /// Number of headers in the snapshot
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn byte_len(&self) -> usize {
+        stream::byte_len(self.count)
+    }
+
+    /// A new snapshot with `block_headers` appended
+    pub fn append(&self, block_headers: &[BlockHeader]) -> Snapshot {
+        let mut chunks: Option<Vec<Bytes>> = None;
+        let mut tail = self.tail.to_vec();
+        let mut count = self.count;
+        for block_header in block_headers {
+            stream::append(&mut tail, count, block_header);
+            count += 1;
+            if count.is_multiple_of(stream::CHUNK_SIZE) {
+                chunks
+                    .get_or_insert_with(|| (*self.chunks).clone())
+                    .push(Bytes::from(tail));
+                tail = Vec::new();
+            }
+        }
+        Snapshot {
+            chunks: chunks.map(Arc::new).unwrap_or_else(|| self.chunks.clone()),
+            tail: Bytes::from(tail),
+            count,
+        }
+    }
+
+    /// A new snapshot with the first `count` headers
+    pub fn truncate(&self, count: usize) -> Snapshot {
+        if count >= self.count {
+            return self.clone();
+        }
+        let full_chunks = count / stream::CHUNK_SIZE;
+        let tail_len = stream::byte_len(count % stream::CHUNK_SIZE);
+        let tail = if full_chunks < self.chunks.len() {
+            self.chunks[full_chunks].slice(0, tail_len)
+        } else {
+            self.tail.slice(0, tail_len)
+        };
+        let chunks = if full_chunks < self.chunks.len() {
+            Arc::new(self.chunks[..full_chunks].to_vec())
+        } else {
+            self.chunks.clone()
+        };
+        Snapshot {
+            chunks,
+            tail,
+            count,
+        }
+    }
+
+    /// The bytes `start..end` as slices of the shared chunks, without copying
+    pub fn slices(&self, start: usize, end: usize) -> io::Result<Vec<Bytes>> {
+        check_range(start, end, self.byte_len())?;
+        let chunk_len = stream::byte_len(stream::CHUNK_SIZE);
+        let mut result = Vec::new();
+        let parts = self.chunks.iter().chain(Some(&self.tail));
+        for (i, part) in parts.enumerate() {
+            let part_start = i * chunk_len;
+            let part_end = part_start + part.len();
+            if part_end <= start || part_start >= end {
+                continue;
+            }
+            let from = start.max(part_start) - part_start;
+            let to = end.min(part_end) - part_start;
+            result.push(part.slice(from, to));
+        }
+        Ok(result)
+    }
+
+    /// The bytes `start..end` copied in a single buffer
+    pub fn read(&self, start: usize, end: usize) -> io::Result<Vec<u8>> {
+        let mut result = Vec::with_capacity(end.saturating_sub(start));
+        for slice in self.slices(start, end)? {
+            result.extend_from_slice(&slice);
+        }
+        Ok(result)
+    }
+}
+
+impl Default for Snapshot {
+    fn default() -> Snapshot {
+        Snapshot::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::header::BlockHeader;
+    use bitcoin::stream;
+    use store::snapshot::Snapshot;
+
+    #[test]
+    pub fn test_snapshot() {
+        let mut block_headers = Vec::new();
+        let mut prev_hash = [0u8; 32];
+        for i in 0..5000u32 {
+            let mut block_header = BlockHeader::new();
+            block_header.prev_blockhash = prev_hash;
+            block_header.nonce = i.to_le_bytes();
+            prev_hash = block_header.hash();
+            block_headers.push(block_header);
+        }
+        let bytes = stream::encode(&block_headers);
+
+        let empty = Snapshot::new();
+        let first = empty.append(&block_headers[..3000]);
+        let second = first.append(&block_headers[3000..]);
+        assert_eq!(empty.byte_len(), 0);
+        assert_eq!(first.len(), 3000);
+        assert_eq!(first.read(0, first.byte_len()).unwrap(), &bytes[..first.byte_len()]);
+        assert_eq!(second.read(0, bytes.len()).unwrap(), bytes);
+        assert_eq!(second.slices(0, bytes.len()).unwrap().len(), 3);
+        assert_eq!(second.read(88000, 180000).unwrap(), &bytes[88000..180000]);
+        assert!(second.read(0, bytes.len() + 1).is_err());
+
+        for count in &[0, 1, 2015, 2016, 2017, 4100] {
+            let truncated = second.truncate(*count);
+            let len = stream::byte_len(*count);
+            assert_eq!(truncated.read(0, len).unwrap(), &bytes[..len]);
+            let restored = truncated.append(&block_headers[*count..]);
+            assert_eq!(restored.read(0, bytes.len()).unwrap(), bytes);
+        }
+    }
+}
@@ -24,11 +24,10 @@ pub trait ToHex {
     fn to_hex(&self) -> String;
 }
 
-static CHARS: &'static [u8] = b"0123456789abcdef";
+static CHARS: &[u8] = b"0123456789abcdef";
 
 impl ToHex for [u8] {
     /// Turn a vector of `u8` bytes into a hexadecimal string.
-
     fn to_hex(&self) -> String {
         let mut v = Vec::with_capacity(self.len() * 2);
         for &byte in self.iter() {
@@ -40,7 +39,7 @@ impl ToHex for [u8] {
     }
 }
 
-impl<'a, T: ?Sized + ToHex> ToHex for &'a T {
+impl<T: ?Sized + ToHex> ToHex for &T {
     fn to_hex(&self) -> String {
         (**self).to_hex()
     }
@@ -50,6 +49,7 @@ impl<'a, T: ?Sized + ToHex> ToHex for &'a T {
 pub trait FromHex {
     /// Converts the value of `self`, interpreted as hexadecimal encoded data,
     /// into an owned vector of bytes, returning the vector.
+    #[allow(clippy::wrong_self_convention)]
     fn from_hex(&self) -> Result<Vec<u8>, FromHexError>;
 }
 
@@ -106,9 +106,9 @@ impl FromHex for str {
             buf <<= 4;
 
             match byte {
-                b'A'...b'F' => buf |= byte - b'A' + 10,
-                b'a'...b'f' => buf |= byte - b'a' + 10,
-                b'0'...b'9' => buf |= byte - b'0',
+                b'A'..=b'F' => buf |= byte - b'A' + 10,
+                b'a'..=b'f' => buf |= byte - b'a' + 10,
+                b'0'..=b'9' => buf |= byte - b'0',
                 b' ' | b'\r' | b'\n' | b'\t' => {
                     buf >>= 4;
                     continue;
@@ -133,7 +133,7 @@ impl FromHex for str {
     }
 }
 
-impl<'a, T: ?Sized + FromHex> FromHex for &'a T {
+impl<T: ?Sized + FromHex> FromHex for &T {
     fn from_hex(&self) -> Result<Vec<u8>, FromHexError> {
         (**self).from_hex()
     }
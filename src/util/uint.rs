@@ -0,0 +1,286 @@
+//! Minimal 256 bits unsigned integer for targets and chain work
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Not, Shl, Shr, Sub};
+
+/// 256 bits unsigned integer, limbs are little endian
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Uint256(pub [u64; 4]);
+
+impl Uint256 {
+    pub fn zero() -> Uint256 {
+        Uint256([0; 4])
+    }
+
+    pub fn from_u64(value: u64) -> Uint256 {
+        Uint256([value, 0, 0, 0])
+    }
+
+    /// Interpret 32 bytes as a little endian number, like a block hash as returned by `hash()`
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Uint256 {
+        let mut limbs: [u64; 4] = [0; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let mut le: [u8; 8] = [0; 8];
+            le.clone_from_slice(&bytes[i * 8..i * 8 + 8]);
+            *limb = u64::from_le_bytes(le);
+        }
+        Uint256(limbs)
+    }
+
+    pub fn to_le_bytes(&self) -> [u8; 32] {
+        let mut result: [u8; 32] = [0; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            result[i * 8..i * 8 + 8].clone_from_slice(&limb.to_le_bytes());
+        }
+        result
+    }
+
+    /// Decode the compact representation used in the `bits` header field,
+    /// negative or overflowing values are returned as zero
+    pub fn from_compact(compact: u32) -> Uint256 {
+        let size = (compact >> 24) as usize;
+        let mantissa = compact & 0x007f_ffff;
+        if compact & 0x0080_0000 != 0 || mantissa == 0 {
+            return Uint256::zero();
+        }
+        if size <= 3 {
+            Uint256::from_u64(u64::from(mantissa >> (8 * (3 - size))))
+        } else if size > 34 || (mantissa > 0xff && size > 33) || (mantissa > 0xffff && size > 32) {
+            Uint256::zero()
+        } else {
+            Uint256::from_u64(u64::from(mantissa)) << (8 * (size - 3))
+        }
+    }
+
+    /// Encode in the compact representation used in the `bits` header field, like bitcoind `GetCompact`
+    pub fn to_compact(&self) -> u32 {
+        let mut size = self.bits().div_ceil(8);
+        let mut compact = match size {
+            0..=3 => (self.0[0] << (8 * (3 - size))) as u32,
+            _ => (*self >> (8 * (size - 3))).0[0] as u32,
+        };
+        if compact & 0x0080_0000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+        compact | (size as u32) << 24
+    }
+
+    /// Multiply by `rhs`, overflowing bits are lost
+    pub fn wrapping_mul_u64(self, rhs: u64) -> Uint256 {
+        let mut result = Uint256::zero();
+        let mut carry: u128 = 0;
+        for (i, limb) in self.0.iter().enumerate() {
+            let product = u128::from(*limb) * u128::from(rhs) + carry;
+            result.0[i] = product as u64;
+            carry = product >> 64;
+        }
+        result
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    /// Number of significant bits
+    pub fn bits(&self) -> usize {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i + 64 - self.0[i].leading_zeros() as usize;
+            }
+        }
+        0
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        self.0[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn set_bit(&mut self, index: usize) {
+        self.0[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn wrapping_add(self, rhs: Uint256) -> Uint256 {
+        let mut result: [u64; 4] = [0; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (sum, overflow_a) = self.0[i].overflowing_add(rhs.0[i]);
+            let (sum, overflow_b) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = overflow_a || overflow_b;
+        }
+        Uint256(result)
+    }
+
+    /// The sum, None if it overflows
+    pub fn checked_add(self, rhs: Uint256) -> Option<Uint256> {
+        let sum = self.wrapping_add(rhs);
+        match sum < self {
+            true => None,
+            false => Some(sum),
+        }
+    }
+
+    pub fn wrapping_sub(self, rhs: Uint256) -> Uint256 {
+        let mut result: [u64; 4] = [0; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (diff, overflow_a) = self.0[i].overflowing_sub(rhs.0[i]);
+            let (diff, overflow_b) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = overflow_a || overflow_b;
+        }
+        Uint256(result)
+    }
+}
+
+impl Ord for Uint256 {
+    fn cmp(&self, other: &Uint256) -> Ordering {
+        for i in (0..4).rev() {
+            match self.0[i].cmp(&other.0[i]) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Uint256 {
+    fn partial_cmp(&self, other: &Uint256) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for Uint256 {
+    type Output = Uint256;
+    fn add(self, rhs: Uint256) -> Uint256 {
+        self.wrapping_add(rhs)
+    }
+}
+
+impl Sub for Uint256 {
+    type Output = Uint256;
+    fn sub(self, rhs: Uint256) -> Uint256 {
+        self.wrapping_sub(rhs)
+    }
+}
+
+impl Not for Uint256 {
+    type Output = Uint256;
+    fn not(self) -> Uint256 {
+        Uint256([!self.0[0], !self.0[1], !self.0[2], !self.0[3]])
+    }
+}
+
+impl Shl<usize> for Uint256 {
+    type Output = Uint256;
+    fn shl(self, shift: usize) -> Uint256 {
+        let mut result: [u64; 4] = [0; 4];
+        let limbs = shift / 64;
+        let bits = shift % 64;
+        for (i, limb) in result.iter_mut().enumerate().skip(limbs) {
+            *limb = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                *limb |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        Uint256(result)
+    }
+}
+
+impl Shr<usize> for Uint256 {
+    type Output = Uint256;
+    fn shr(self, shift: usize) -> Uint256 {
+        let mut result: [u64; 4] = [0; 4];
+        let limbs = shift / 64;
+        let bits = shift % 64;
+        for (i, limb) in result.iter_mut().take(4usize.saturating_sub(limbs)).enumerate() {
+            *limb = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs + 1 < 4 {
+                *limb |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        Uint256(result)
+    }
+}
+
+impl Div for Uint256 {
+    type Output = Uint256;
+    /// Long division, panics when dividing by zero
+    fn div(self, rhs: Uint256) -> Uint256 {
+        assert!(!rhs.is_zero(), "division by zero");
+        let mut quotient = Uint256::zero();
+        let mut remainder = Uint256::zero();
+        for i in (0..self.bits()).rev() {
+            let carry = remainder.bit(255);
+            remainder = remainder << 1;
+            if self.bit(i) {
+                remainder.0[0] |= 1;
+            }
+            if carry || remainder >= rhs {
+                remainder = remainder.wrapping_sub(rhs);
+                quotient.set_bit(i);
+            }
+        }
+        quotient
+    }
+}
+
+impl fmt::Debug for Uint256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:016x}{:016x}{:016x}{:016x}",
+            self.0[3], self.0[2], self.0[1], self.0[0]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use util::uint::Uint256;
+
+    #[test]
+    pub fn test_from_compact() {
+        let target = Uint256::from_compact(0x1d00ffff);
+        assert_eq!(
+            format!("{:?}", target),
+            "00000000ffff0000000000000000000000000000000000000000000000000000"
+        );
+        assert_eq!(Uint256::from_compact(0x01003456), Uint256::zero());
+        assert_eq!(Uint256::from_compact(0x02123456), Uint256::from_u64(0x1234));
+        assert_eq!(Uint256::from_compact(0x04923456), Uint256::zero());
+        for compact in &[0x1d00ffff, 0x1b0404cb, 0x02123400, 0x05009234] {
+            assert_eq!(Uint256::from_compact(*compact).to_compact(), *compact);
+        }
+    }
+
+    #[test]
+    pub fn test_arithmetic() {
+        let a = Uint256([u64::MAX, 0, 0, 0]);
+        let one = Uint256::from_u64(1);
+        assert_eq!(a + one, Uint256([0, 1, 0, 0]));
+        assert_eq!(Uint256([0, 1, 0, 0]) - one, a);
+        assert_eq!(one << 200 >> 200, one);
+        assert_eq!((one << 130).bits(), 131);
+        assert_eq!((one << 200) / (one << 100), one << 100);
+        assert_eq!(Uint256::from_u64(100) / Uint256::from_u64(7), Uint256::from_u64(14));
+        assert!(one << 64 > a);
+        assert_eq!(a.wrapping_mul_u64(2), Uint256([u64::MAX - 1, 1, 0, 0]));
+        assert_eq!(a.checked_add(one), Some(Uint256([0, 1, 0, 0])));
+        assert_eq!(Uint256([0, 0, 0, u64::MAX]).checked_add(Uint256([0, 0, 0, 1])), None);
+        assert_eq!(Uint256([u64::MAX; 4]).checked_add(one), None);
+    }
+
+    #[test]
+    pub fn test_le_bytes() {
+        let mut bytes: [u8; 32] = [0; 32];
+        bytes[0] = 1;
+        bytes[31] = 0x80;
+        let value = Uint256::from_le_bytes(bytes);
+        assert_eq!(value.bits(), 256);
+        assert_eq!(value.to_le_bytes(), bytes);
+    }
+}